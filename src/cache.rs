@@ -0,0 +1,178 @@
+//! A key-value store for data that is expensive to obtain from SoundCloud.
+//!
+//! Entries are kept in memory and, if a directory is configured, also written to disk so they
+//! survive a remount. Keys may contain slashes, which are mapped to subdirectories.
+
+use log::*;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// The number of bytes that is kept in memory. Entries that do not fit are still on disk.
+const MEMORY_LIMIT: usize = 64 << 20;
+
+pub struct Cache {
+    dir: Option<PathBuf>,
+    memory: Mutex<Memory>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl Cache {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        Cache {
+            dir,
+            memory: Mutex::new(Memory {
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                len: 0,
+                limit: MEMORY_LIMIT,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The number of lookups that did and did not find an entry.
+    pub fn stats(&self) -> (u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        let value = self.lookup(key);
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    fn lookup(&self, key: &str) -> Option<Vec<u8>> {
+        if let Some(value) = self.memory.lock().unwrap().entries.get(key) {
+            return Some(value.clone());
+        }
+        let path = self.dir.as_ref()?.join(key);
+        match fs::read(&path) {
+            Ok(value) => {
+                trace!("cache: loaded {}", key);
+                self.memory.lock().unwrap().insert(key, value.clone());
+                Some(value)
+            }
+            Err(err) => {
+                if err.kind() != io::ErrorKind::NotFound {
+                    warn!("cache: could not read {}: {}", path.display(), err);
+                }
+                None
+            }
+        }
+    }
+
+    /// Stores an entry. Failing to write to disk is not an error, the entry is then only kept in
+    /// memory.
+    pub fn put(&self, key: &str, value: &[u8]) {
+        let mut memory = self.memory.lock().unwrap();
+        memory.insert(key, value.to_vec());
+        if let Some(dir) = &self.dir {
+            let path = dir.join(key);
+            // The entry is written to a temporary file first so a concurrent reader never sees a
+            // partially written entry.
+            let tmp_path = path.with_extension("tmp");
+            let rs = path
+                .parent()
+                .map(fs::create_dir_all)
+                .unwrap_or(Ok(()))
+                .and_then(|_| fs::write(&tmp_path, value))
+                .and_then(|_| fs::rename(&tmp_path, &path));
+            if let Err(err) = rs {
+                warn!("cache: could not write {}: {}", path.display(), err);
+            }
+        }
+    }
+}
+
+/// The entries that are kept in memory. The oldest entries are dropped once they take up more
+/// than the limit.
+struct Memory {
+    entries: HashMap<String, Vec<u8>>,
+    /// The keys in the order in which they were inserted.
+    order: VecDeque<String>,
+    len: usize,
+    limit: usize,
+}
+
+impl Memory {
+    fn insert(&mut self, key: &str, value: Vec<u8>) {
+        if let Some(old) = self.entries.remove(key) {
+            self.len -= old.len();
+            self.order.retain(|k| k != key);
+        }
+        self.len += value.len();
+        self.entries.insert(key.to_string(), value);
+        self.order.push_back(key.to_string());
+        while self.len > self.limit {
+            let oldest = match self.order.pop_front() {
+                Some(k) => k,
+                None => break,
+            };
+            if let Some(value) = self.entries.remove(&oldest) {
+                self.len -= value.len();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("soundcloud-fs-cache-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn memory() {
+        let cache = Cache::new(None);
+        assert_eq!(None, cache.get("a/1"));
+        cache.put("a/1", b"hello");
+        assert_eq!(Some(b"hello".to_vec()), cache.get("a/1"));
+        assert_eq!((1, 1), cache.stats());
+    }
+
+    #[test]
+    fn memory_limit() {
+        let dir = test_dir("memory-limit");
+        let cache = Cache::new(Some(dir));
+        cache.memory.lock().unwrap().limit = 8;
+        cache.put("a/1", b"hello");
+        cache.put("a/2", b"world");
+        assert_eq!(cache.memory.lock().unwrap().order, ["a/2"]);
+
+        // Entries that were dropped from memory are read back from disk.
+        assert_eq!(Some(b"hello".to_vec()), cache.get("a/1"));
+        assert_eq!(cache.memory.lock().unwrap().order, ["a/1"]);
+        assert_eq!(5, cache.memory.lock().unwrap().len);
+    }
+
+    #[test]
+    fn persistent() {
+        let dir = test_dir("persistent");
+        Cache::new(Some(dir.clone())).put("a/1", b"hello");
+
+        let cache = Cache::new(Some(dir.clone()));
+        assert_eq!(Some(b"hello".to_vec()), cache.get("a/1"));
+        assert_eq!(None, cache.get("a/2"));
+        assert!(!dir.join("a/1.tmp").exists());
+    }
+}
@@ -0,0 +1,204 @@
+//! Chapters are read from tracklists in track descriptions, as is common for DJ mixes:
+//!
+//! ```text
+//! 00:00 Artist - Title
+//! 04:12 Another Artist - Another Title
+//! 1:02:45 Last Artist - Last Title
+//! ```
+
+use crate::id3tag::TitleRules;
+use crate::soundcloud;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::fmt::Write;
+
+/// Tracklists need at least this many entries, a single timestamp is probably just a remark.
+const MIN_CHAPTERS: usize = 2;
+
+/// Where chapters are written to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ChapterMode {
+    None,
+    /// CHAP and CTOC frames in the ID3 tag.
+    ID3,
+    /// A cue sheet next to the audio file.
+    Cue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Chapter {
+    pub start_ms: u64,
+    pub end_ms: u64,
+    pub title: String,
+}
+
+/// Reads the tracklist from the description of a track. The times are shifted by offset_ms to
+/// account for anything that precedes the audio in the file.
+pub fn from_track(track: &soundcloud::Track, offset_ms: u64) -> Vec<Chapter> {
+    lazy_static! {
+        static ref RE_LINE: Regex = Regex::new(
+            r"^\s*(?:\d+[.)]\s*)?[\[(]?(?:(\d{1,2}):)?(\d{1,2}):(\d{2})[\])]?\s*(?:[-–—|:.]\s+)?(.+?)\s*$"
+        )
+        .unwrap();
+    }
+    let description = match &track.description {
+        Some(v) => v,
+        None => return Vec::new(),
+    };
+    let duration_ms = track.duration_ms.max(0) as u64;
+
+    let mut starts = Vec::new();
+    for line in description.lines() {
+        let caps = match RE_LINE.captures(line) {
+            Some(v) => v,
+            None => continue,
+        };
+        let num = |i| {
+            caps.get(i)
+                .map_or(0, |m| m.as_str().parse::<u64>().unwrap())
+        };
+        let start_ms = ((num(1) * 60 + num(2)) * 60 + num(3)) * 1000;
+        // A tracklist is in order and within the track. Anything else is not a tracklist.
+        let in_order = starts.last().is_none_or(|&(prev, _)| prev < start_ms);
+        if !in_order || start_ms >= duration_ms {
+            return Vec::new();
+        }
+        starts.push((start_ms, caps[4].to_string()));
+    }
+    if starts.len() < MIN_CHAPTERS {
+        return Vec::new();
+    }
+
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|&(start, _)| start)
+        .chain(Some(duration_ms));
+    starts
+        .iter()
+        .zip(ends)
+        .map(|((start_ms, title), end_ms)| Chapter {
+            start_ms: start_ms + offset_ms,
+            end_ms: end_ms + offset_ms,
+            title: title.clone(),
+        })
+        .collect()
+}
+
+/// Writes a cue sheet that splits the audio file into its chapters.
+pub fn cue_sheet(
+    track: &soundcloud::Track,
+    audio_file: &str,
+    chapters: &[Chapter],
+    title_rules: &TitleRules,
+) -> String {
+    let mut cue = String::new();
+    let parsed = title_rules.parse(&track.title, &track.user.username);
+    writeln!(cue, "PERFORMER {}", cue_string(&parsed.artist)).unwrap();
+    writeln!(cue, "TITLE {}", cue_string(&parsed.title)).unwrap();
+    writeln!(cue, "FILE {} MP3", cue_string(audio_file)).unwrap();
+    for (i, chapter) in chapters.iter().enumerate() {
+        let parsed = title_rules.parse(&chapter.title, "");
+        writeln!(cue, "  TRACK {:02} AUDIO", i + 1).unwrap();
+        writeln!(cue, "    TITLE {}", cue_string(&parsed.title)).unwrap();
+        if !parsed.artist.is_empty() {
+            writeln!(cue, "    PERFORMER {}", cue_string(&parsed.artist)).unwrap();
+        }
+        // Cue sheets count time in minutes, seconds and frames of 1/75th of a second.
+        let (secs, ms) = (chapter.start_ms / 1000, chapter.start_ms % 1000);
+        let index = format!("{:02}:{:02}:{:02}", secs / 60, secs % 60, ms * 75 / 1000);
+        writeln!(cue, "    INDEX 01 {}", index).unwrap();
+    }
+    cue
+}
+
+fn cue_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn track(description: &str) -> soundcloud::Track {
+        let mut track = soundcloud::Track::example();
+        track.description = Some(description.to_string());
+        track.duration_ms = 4_000_000;
+        track
+    }
+
+    fn chapter(start_ms: u64, end_ms: u64, title: &str) -> Chapter {
+        Chapter {
+            start_ms,
+            end_ms,
+            title: title.to_string(),
+        }
+    }
+
+    #[test]
+    fn tracklist() {
+        let track = track(
+            "Recorded live.\n\
+             \n\
+             Tracklist:\n\
+             00:00 Bicep - Glue\n\
+             [04:12] Mall Grab - Can't\n\
+             3. 1:02:45 - Tourist - Elixir \n",
+        );
+        assert_eq!(
+            vec![
+                chapter(0, 252_000, "Bicep - Glue"),
+                chapter(252_000, 3_765_000, "Mall Grab - Can't"),
+                chapter(3_765_000, 4_000_000, "Tourist - Elixir"),
+            ],
+            from_track(&track, 0),
+        );
+        assert_eq!(
+            chapter(1_000, 253_000, "Bicep - Glue"),
+            from_track(&track, 1_000)[0],
+        );
+    }
+
+    #[test]
+    fn not_a_tracklist() {
+        assert_eq!(
+            Vec::<Chapter>::new(),
+            from_track(&track("The drop at 1:30!"), 0)
+        );
+        assert_eq!(
+            Vec::<Chapter>::new(),
+            from_track(&track("1:30 the drop"), 0)
+        );
+        assert_eq!(
+            Vec::<Chapter>::new(),
+            from_track(&track("04:00 B\n00:00 A"), 0)
+        );
+        assert_eq!(
+            Vec::<Chapter>::new(),
+            from_track(&track("00:00 A\n2:00:00 B"), 0)
+        );
+    }
+
+    #[test]
+    fn cue() {
+        let track = soundcloud::Track::example();
+        let chapters = vec![
+            chapter(0, 1_000, "Bicep - Glue"),
+            chapter(3_765_500, 4_000_000, "\"Untitled\""),
+        ];
+        let rules = TitleRules::new::<&str>(&[]).unwrap();
+        assert_eq!(
+            "PERFORMER \"Wright and Bastard\"\n\
+             TITLE \"The Fat Dandy Butterfly Slims\"\n\
+             FILE \"a.mp3\" MP3\n\
+             \x20 TRACK 01 AUDIO\n\
+             \x20   TITLE \"Glue\"\n\
+             \x20   PERFORMER \"Bicep\"\n\
+             \x20   INDEX 01 00:00:00\n\
+             \x20 TRACK 02 AUDIO\n\
+             \x20   TITLE \"'Untitled'\"\n\
+             \x20   INDEX 01 62:45:37\n",
+            cue_sheet(&track, "a.mp3", &chapters, &rules),
+        );
+    }
+}
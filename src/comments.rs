@@ -0,0 +1,98 @@
+//! Timed comments are placed at a position in a track and are shown along the waveform on the
+//! website. They are written in a format similar to LRC lyrics:
+//!
+//! ```text
+//! [00:12.50] someone: What a drop!
+//! ```
+
+use crate::soundcloud;
+use std::fmt::Write;
+
+/// The comments that are placed at a position in the track, in order, as (time, text) pairs. The
+/// text includes the author. The times are shifted by offset_ms to account for anything that
+/// precedes the audio in the file.
+pub fn timed(comments: &[soundcloud::Comment], offset_ms: u64) -> Vec<(u64, String)> {
+    let mut timed: Vec<_> = comments
+        .iter()
+        .filter_map(|comment| {
+            let time = comment.timestamp.filter(|&t| t >= 0)? as u64 + offset_ms;
+            let body = comment
+                .body
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            Some((time, format!("{}: {}", comment.user.username, body)))
+        })
+        .collect();
+    // Sorting is stable, so comments at the same position stay in the order of the API.
+    timed.sort_by_key(|&(time, _)| time);
+    timed
+}
+
+pub fn lrc(track: &soundcloud::Track, timed: &[(u64, String)]) -> String {
+    let mut lrc = String::new();
+    writeln!(lrc, "[ti:{}]", track.title).unwrap();
+    writeln!(lrc, "[ar:{}]", track.user.username).unwrap();
+    for (time, text) in timed {
+        let (secs, ms) = (time / 1000, time % 1000);
+        writeln!(
+            lrc,
+            "[{:02}:{:02}.{:02}] {}",
+            secs / 60,
+            secs % 60,
+            ms / 10,
+            text
+        )
+        .unwrap();
+    }
+    lrc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comment(timestamp: Option<i64>, username: &str, body: &str) -> soundcloud::Comment {
+        serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "body": body,
+            "timestamp": timestamp,
+            "created_at": "2019/05/04 10:00:00 +0000",
+            "user": {
+                "id": 2,
+                "permalink": username.to_lowercase(),
+                "username": username,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn comments() {
+        let comments = vec![
+            comment(Some(62_345), "B", "second"),
+            comment(None, "C", "not placed"),
+            comment(Some(12_500), "A", "first\nline"),
+            comment(Some(62_345), "D", "third"),
+        ];
+        let timed = timed(&comments, 1_000);
+        assert_eq!(
+            vec![
+                (13_500, "A: first line".to_string()),
+                (63_345, "B: second".to_string()),
+                (63_345, "D: third".to_string()),
+            ],
+            timed
+        );
+
+        let track = soundcloud::Track::example();
+        assert_eq!(
+            "[ti:The Fat Dandy Butterfly Slims]\n\
+             [ar:Wright and Bastard]\n\
+             [00:13.50] A: first line\n\
+             [01:03.34] B: second\n\
+             [01:03.34] D: third\n",
+            lrc(&track, &timed)
+        );
+    }
+}
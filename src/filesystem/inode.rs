@@ -0,0 +1,216 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+/// The key under which an inode number is allocated.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InodeKey {
+    Identity(Identity),
+    /// Nodes without an identity are keyed on the inode of their parent and their name.
+    Path(u64, String),
+}
+
+impl InodeKey {
+    pub fn for_child<N: NodeType>(parent_ino: u64, name: &str, node: &Node<N>) -> Self {
+        match node.identity() {
+            // Directories must not be hard linked, tools that walk the tree would visit them more
+            // than once or consider the tree to be broken.
+            Some(id) if !matches!(node, Node::Directory(_)) => InodeKey::Identity(id),
+            _ => InodeKey::Path(parent_ino, name.to_string()),
+        }
+    }
+}
+
+struct Inode {
+    key: InodeKey,
+    refs: u64,
+    /// The directories and names this inode has been seen under, used as the link count.
+    links: HashSet<(u64, String)>,
+}
+
+/// InodeTable hands out inode numbers for keys. A number stays allocated to its key for as long as
+/// it is referenced and is never handed out to another key in the meantime.
+pub struct InodeTable {
+    inodes: HashMap<u64, Inode>,
+    by_key: HashMap<InodeKey, u64>,
+    /// The inode linked under each name, by the inode of the directory.
+    children: HashMap<u64, HashMap<String, u64>>,
+    next_ino: u64,
+}
+
+impl InodeTable {
+    pub fn new() -> Self {
+        InodeTable {
+            inodes: HashMap::new(),
+            by_key: HashMap::new(),
+            children: HashMap::new(),
+            next_ino: INO_ROOT + 1,
+        }
+    }
+
+    pub fn get(&self, key: &InodeKey) -> Option<u64> {
+        self.by_key.get(key).cloned()
+    }
+
+    /// Takes a reference to the inode number of the key, allocating one if needed.
+    pub fn acquire(&mut self, key: InodeKey) -> u64 {
+        if let Some(&ino) = self.by_key.get(&key) {
+            self.inodes.get_mut(&ino).unwrap().refs += 1;
+            return ino;
+        }
+        let ino = self.allocate();
+        self.by_key.insert(key.clone(), ino);
+        self.inodes.insert(
+            ino,
+            Inode {
+                key,
+                refs: 1,
+                links: HashSet::new(),
+            },
+        );
+        ino
+    }
+
+    /// Drops a reference to an inode number. The number is freed once it is no longer referenced,
+    /// together with the links to and from it.
+    pub fn release(&mut self, ino: u64) {
+        let inode = match self.inodes.get_mut(&ino) {
+            Some(v) => v,
+            None => return,
+        };
+        inode.refs -= 1;
+        if inode.refs != 0 {
+            return;
+        }
+        let inode = self.inodes.remove(&ino).unwrap();
+        self.by_key.remove(&inode.key);
+        for (parent_ino, name) in inode.links {
+            if let Some(children) = self.children.get_mut(&parent_ino) {
+                children.remove(&name);
+            }
+        }
+        for (name, child_ino) in self.children.remove(&ino).unwrap_or_default() {
+            if let Some(child) = self.inodes.get_mut(&child_ino) {
+                child.links.remove(&(ino, name));
+            }
+        }
+    }
+
+    /// Records that the inode is reachable from the specified directory under a name.
+    pub fn link(&mut self, ino: u64, parent_ino: u64, name: &str) {
+        if !self.inodes.contains_key(&ino) {
+            return;
+        }
+        let children = self.children.entry(parent_ino).or_default();
+        if let Some(old_ino) = children.insert(name.to_string(), ino) {
+            if old_ino != ino {
+                if let Some(old) = self.inodes.get_mut(&old_ino) {
+                    old.links.remove(&(parent_ino, name.to_string()));
+                }
+            }
+        }
+        let inode = self.inodes.get_mut(&ino).unwrap();
+        inode.links.insert((parent_ino, name.to_string()));
+    }
+
+    /// Records that the name in the specified directory no longer refers to an inode.
+    pub fn unlink(&mut self, parent_ino: u64, name: &str) {
+        let child_ino = match self.children.get_mut(&parent_ino) {
+            Some(children) => children.remove(name),
+            None => None,
+        };
+        if let Some(child) = child_ino.and_then(|ino| self.inodes.get_mut(&ino)) {
+            child.links.remove(&(parent_ino, name.to_string()));
+        }
+    }
+
+    pub fn links(&self, ino: u64) -> u32 {
+        self.inodes
+            .get(&ino)
+            .map(|inode| inode.links.len().max(1) as u32)
+            .unwrap_or(1)
+    }
+
+    fn allocate(&mut self) -> u64 {
+        loop {
+            let ino = self.next_ino;
+            self.next_ino = self.next_ino.wrapping_add(1);
+            if ino > INO_ROOT && !self.inodes.contains_key(&ino) {
+                return ino;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> InodeKey {
+        InodeKey::Path(INO_ROOT, name.to_string())
+    }
+
+    #[test]
+    fn acquire_release() {
+        let mut table = InodeTable::new();
+
+        let a = table.acquire(path("a"));
+        let b = table.acquire(path("b"));
+        assert_ne!(a, b);
+        assert_ne!(INO_ROOT, a);
+        assert_eq!(a, table.acquire(path("a")));
+
+        table.release(a);
+        assert_eq!(Some(a), table.get(&path("a")));
+        table.release(a);
+        assert_eq!(None, table.get(&path("a")));
+    }
+
+    #[test]
+    fn allocate_skips_used() {
+        let mut table = InodeTable::new();
+        let a = table.acquire(path("a"));
+        assert_eq!(INO_ROOT + 1, a);
+
+        // After wrapping around, neither the reserved numbers nor numbers in use are handed out.
+        table.next_ino = u64::MAX;
+        assert_eq!(u64::MAX, table.acquire(path("b")));
+        assert_eq!(a + 1, table.acquire(path("c")));
+    }
+
+    #[test]
+    fn links() {
+        let mut table = InodeTable::new();
+        let a = table.acquire(path("a"));
+        assert_eq!(1, table.links(a));
+
+        table.link(a, 10, "a");
+        table.link(a, 10, "a");
+        assert_eq!(1, table.links(a));
+        table.link(a, 11, "a");
+        assert_eq!(2, table.links(a));
+
+        table.unlink(11, "a");
+        assert_eq!(1, table.links(a));
+        table.unlink(11, "a");
+        assert_eq!(1, table.links(a));
+    }
+
+    #[test]
+    fn release_unlinks() {
+        let mut table = InodeTable::new();
+        let a = table.acquire(path("a"));
+        let dir = table.acquire(path("dir"));
+        table.link(a, INO_ROOT, "a");
+        table.link(a, dir, "a");
+        assert_eq!(2, table.links(a));
+
+        // Once the directory is freed, its number can be handed out again without its old entries.
+        table.release(dir);
+        assert_eq!(1, table.links(a));
+        let b = table.acquire(path("b"));
+        table.link(b, INO_ROOT, "b");
+        table.release(b);
+        assert!(!table.children[&INO_ROOT].contains_key("b"));
+        assert_eq!(Some(&a), table.children[&INO_ROOT].get("a"));
+    }
+}
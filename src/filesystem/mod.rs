@@ -1,32 +1,45 @@
+mod inode;
 mod node;
 mod nodecache;
 
 use chrono::{DateTime, Utc};
 use fuse;
 use log::*;
-use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
 use std::ffi;
-use std::hash::{Hash, Hasher};
 use std::io::{self, Read, Seek};
 use std::os;
 use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use self::inode::*;
 pub use self::node::*;
 pub use self::node::{Metadata, NodeType};
 pub use self::nodecache::*;
 pub use crate::ioutil::*;
 
-const INO_ROOT: u64 = 1;
+pub(crate) const INO_ROOT: u64 = 1;
+const BLOCK_SIZE: u64 = 1024;
+const NAME_MAX: u32 = 255;
+
+// Counters for `stats`. They are global so they can be read from within the nodes.
+static OPEN_FILES: AtomicU64 = AtomicU64::new(0);
+static OPEN_DIRS: AtomicU64 = AtomicU64::new(0);
+static DIR_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+static DIR_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
 
 pub struct FS<N>
 where
     N: NodeType,
 {
-    nodes: HashMap<u64, Node<N>>,
+    nodes: HashMap<u64, Entry<N>>,
+    inodes: InodeTable,
 
     read_handles: HashMap<u64, <N::File as File>::Reader>,
-    next_read_handle: u64,
+    /// The inodes of the files that are open for writing, by handle.
+    write_handles: HashMap<u64, u64>,
+    next_file_handle: u64,
 
     readdir_handles: HashMap<u64, Vec<(String, Node<N>, u64)>>,
     next_readdir_handle: u64,
@@ -35,17 +48,77 @@ where
     gid: u32,
 }
 
+struct Entry<N: NodeType> {
+    node: Node<N>,
+    /// The size as it was last reported to the kernel.
+    size: u64,
+    /// The number of times the kernel has looked up this node minus the number of times it has
+    /// forgotten it. The entry is evicted when this reaches zero.
+    lookups: u64,
+}
+
+impl<N: NodeType> Entry<N> {
+    fn new(node: Node<N>) -> Self {
+        Entry {
+            node,
+            size: 0,
+            lookups: 0,
+        }
+    }
+}
+
+/// Filesystem statistics as produced by `statfs`. There is never any free space or room for more
+/// files.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatFs {
+    pub blocks: u64,
+    pub files: u64,
+    pub block_size: u32,
+    pub name_max: u32,
+}
+
+/// Counts of what the filesystem is doing, as produced by `stats`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stats {
+    pub open_files: u64,
+    pub open_dirs: u64,
+    /// Lookups and listings that were answered from a cached directory listing.
+    pub dir_cache_hits: u64,
+    pub dir_cache_misses: u64,
+}
+
+pub fn stats() -> Stats {
+    Stats {
+        open_files: OPEN_FILES.load(Ordering::Relaxed),
+        open_dirs: OPEN_DIRS.load(Ordering::Relaxed),
+        dir_cache_hits: DIR_CACHE_HITS.load(Ordering::Relaxed),
+        dir_cache_misses: DIR_CACHE_MISSES.load(Ordering::Relaxed),
+    }
+}
+
+/// A single directory entry as produced by `readdir`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DirEntry {
+    pub ino: u64,
+    /// The offset at which reading should continue to get the entry after this one.
+    pub offset: i64,
+    pub kind: fuse::FileType,
+    pub name: String,
+}
+
 impl<'a, N> FS<N>
 where
     N: NodeType,
 {
     pub fn new(root: &N, uid: u32, gid: u32) -> Self {
         let mut nodes = HashMap::new();
-        nodes.insert(INO_ROOT, Node::Directory(root.root()));
+        nodes.insert(INO_ROOT, Entry::new(Node::Directory(root.root())));
         FS {
             nodes,
+            inodes: InodeTable::new(),
             read_handles: HashMap::new(),
-            next_read_handle: 1,
+            write_handles: HashMap::new(),
+            next_file_handle: 1,
             readdir_handles: HashMap::new(),
             next_readdir_handle: 1,
             uid,
@@ -54,142 +127,469 @@ where
     }
 }
 
-impl<N> fuse::Filesystem for FS<N>
+// The operations below implement the logic behind the FUSE callbacks. They do not depend on a
+// kernel mount so they can also be driven directly, e.g. from tests. Errors are errno values.
+impl<N> FS<N>
 where
     N: NodeType,
 {
-    fn init(&mut self, _req: &fuse::Request) -> Result<(), os::raw::c_int> {
-        trace!("fuse init");
-        Ok(())
-    }
-
-    fn destroy(&mut self, _req: &fuse::Request) {
-        trace!("fuse destroy");
-    }
-
-    fn lookup(
-        &mut self,
-        _req: &fuse::Request,
-        parent_ino: u64,
-        os_name: &ffi::OsStr,
-        reply: fuse::ReplyEntry,
-    ) {
-        let name = os_name.to_string_lossy();
-        trace!("fuse lookup, {}, {}", parent_ino, name);
-
+    pub fn lookup_child(&mut self, parent_ino: u64, name: &str) -> Result<fuse::FileAttr, i32> {
         let child = {
             let parent = match self.nodes.get(&parent_ino) {
-                Some(v) => v,
+                Some(v) => &v.node,
                 None => {
                     error!("fuse: no node for inode {}", parent_ino);
-                    reply.error(libc::ENOENT);
-                    return;
+                    return Err(libc::ENOENT);
                 }
             };
-            let dir = parent
-                .directory()
-                .expect("can not call lookup on a non-directory");
-            match dir.file_by_name(&name) {
+            let dir = parent.directory().ok_or(libc::ENOTDIR)?;
+            match dir.file_by_name(name) {
                 Ok(v) => v,
                 Err(err) => {
                     if err.errno() != libc::ENOENT {
                         error!("fuse: could not get child {}: {}", name, err);
                     }
-                    reply.error(err.errno());
-                    return;
+                    return Err(err.errno());
                 }
             }
         };
+        self.insert_child(parent_ino, name, child)
+    }
 
-        let child_ino = inode_for_child(parent_ino, &name);
+    /// Adds a node that has been looked up or created to the node table.
+    fn insert_child(
+        &mut self,
+        parent_ino: u64,
+        name: &str,
+        child: Node<N>,
+    ) -> Result<fuse::FileAttr, i32> {
+        // Every entry in the node table holds one reference to its inode number.
+        let key = InodeKey::for_child(parent_ino, name, &child);
+        let child_ino = match self.inodes.get(&key) {
+            Some(ino) if self.nodes.contains_key(&ino) => ino,
+            _ => {
+                let ino = self.inodes.acquire(key);
+                self.nodes.insert(ino, Entry::new(child));
+                ino
+            }
+        };
+        self.inodes.link(child_ino, parent_ino, name);
 
-        let attrs = match attrs_for_file(&child, child_ino, self.uid, self.gid) {
+        let attrs = match self.attr(child_ino) {
             Ok(v) => v,
             Err(err) => {
-                error!("fuse: can not get attrs for {}: {}", child_ino, err);
-                reply.error(err.errno());
+                if self.nodes[&child_ino].lookups == 0 {
+                    self.evict(child_ino);
+                }
+                return Err(err);
+            }
+        };
+        let entry = self.nodes.get_mut(&child_ino).unwrap();
+        entry.lookups += 1;
+        Ok(attrs)
+    }
+
+    /// Decrements the lookup count of a node, evicting it from the node table once the kernel no
+    /// longer references it. The root is never evicted.
+    pub fn forget_node(&mut self, ino: u64, nlookup: u64) {
+        let entry = match self.nodes.get_mut(&ino) {
+            Some(v) => v,
+            None => {
+                warn!("fuse: forget for unknown inode {}", ino);
                 return;
             }
         };
+        entry.lookups = entry.lookups.saturating_sub(nlookup);
+        if entry.lookups == 0 && ino != INO_ROOT {
+            self.evict(ino);
+        }
+    }
 
-        self.nodes.insert(child_ino, child);
+    fn evict(&mut self, ino: u64) {
+        trace!("evicting inode {}", ino);
+        self.nodes.remove(&ino);
+        self.inodes.release(ino);
+    }
 
-        let now = time::now().to_timespec();
-        reply.entry(&now, &attrs, 0);
+    pub fn attr(&mut self, ino: u64) -> Result<fuse::FileAttr, i32> {
+        let entry = self.nodes.get_mut(&ino).ok_or(libc::ENOENT)?;
+        let mut attrs = attrs_for_file(&entry.node, ino, self.uid, self.gid).map_err(|err| {
+            error!("fuse: can not get attrs for {}: {}", ino, err);
+            err.errno()
+        })?;
+        entry.size = attrs.size;
+        // Nodes that can be reached through multiple directories are presented as hard links.
+        if attrs.kind != fuse::FileType::Directory {
+            attrs.nlink = self.inodes.links(ino);
+        }
+        Ok(attrs)
     }
 
-    fn getattr(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyAttr) {
-        trace!("fuse getattr: {}", ino);
+    pub fn make_symlink(
+        &mut self,
+        parent_ino: u64,
+        name: &str,
+        target: &Path,
+    ) -> Result<fuse::FileAttr, i32> {
+        let child = {
+            let parent = &self.nodes.get(&parent_ino).ok_or(libc::ENOENT)?.node;
+            let dir = parent.directory().ok_or(libc::ENOTDIR)?;
+            dir.symlink(name, target).map_err(|err| {
+                error!("fuse: could not link {} to {:?}: {}", name, target, err);
+                err.errno()
+            })?
+        };
+        self.insert_child(parent_ino, name, child)
+    }
 
-        if let Some(node) = self.nodes.get(&ino) {
-            let attrs = match attrs_for_file(&node, ino, self.uid, self.gid) {
-                Ok(v) => v,
-                Err(err) => {
-                    error!("fuse: can not get attrs for {}: {}", ino, err);
-                    reply.error(err.errno());
-                    return;
-                }
-            };
-            let ttl = (time::now() + time::Duration::seconds(30)).to_timespec();
-            reply.attr(&ttl, &attrs);
-        } else {
-            reply.error(libc::ENOENT);
-        }
+    pub fn unlink_child(&mut self, parent_ino: u64, name: &str) -> Result<(), i32> {
+        let parent = &self.nodes.get(&parent_ino).ok_or(libc::ENOENT)?.node;
+        let dir = parent.directory().ok_or(libc::ENOTDIR)?;
+        dir.unlink(name).map_err(|err| {
+            error!("fuse: could not unlink {}: {}", name, err);
+            err.errno()
+        })?;
+        self.inodes.unlink(parent_ino, name);
+        Ok(())
     }
 
-    fn readlink(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyData) {
-        trace!("fuse readlink: ino={}", ino);
+    pub fn make_dir(&mut self, parent_ino: u64, name: &str) -> Result<fuse::FileAttr, i32> {
+        let child = {
+            let parent = &self.nodes.get(&parent_ino).ok_or(libc::ENOENT)?.node;
+            let dir = parent.directory().ok_or(libc::ENOTDIR)?;
+            dir.mkdir(name).map_err(|err| {
+                error!("fuse: could not create directory {}: {}", name, err);
+                err.errno()
+            })?
+        };
+        self.insert_child(parent_ino, name, child)
+    }
+
+    pub fn remove_dir(&mut self, parent_ino: u64, name: &str) -> Result<(), i32> {
+        let parent = &self.nodes.get(&parent_ino).ok_or(libc::ENOENT)?.node;
+        let dir = parent.directory().ok_or(libc::ENOTDIR)?;
+        dir.rmdir(name).map_err(|err| {
+            error!("fuse: could not remove directory {}: {}", name, err);
+            err.errno()
+        })?;
+        self.inodes.unlink(parent_ino, name);
+        Ok(())
+    }
+
+    pub fn read_link(&self, ino: u64) -> Result<PathBuf, i32> {
+        let node = &self.nodes.get(&ino).ok_or(libc::ENOENT)?.node;
+        let symlink = node.symlink().ok_or(libc::EINVAL)?;
+        symlink.read_link().map_err(|err| {
+            error!("fuse: could not read symlink: {}", err);
+            err.errno()
+        })
+    }
+
+    pub fn open_file(&mut self, ino: u64, flags: u32) -> Result<u64, i32> {
+        const WRITE_FLAGS: i32 = libc::O_APPEND | libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC;
+        let write = flags & libc::O_ACCMODE as u32 != libc::O_RDONLY as u32
+            || flags & WRITE_FLAGS as u32 != 0;
 
-        if let Some(node) = self.nodes.get(&ino) {
-            let symlink = node
-                .symlink()
-                .expect("can not call readlink on a non-symlink");
-            let path = match symlink.read_link() {
+        let node = match self.nodes.get(&ino) {
+            Some(v) => &v.node,
+            None => {
+                error!("fuse: no such inode: {}", ino);
+                return Err(libc::ENOENT);
+            }
+        };
+        let file = node.file().ok_or(libc::EISDIR)?;
+        let fh = self.next_file_handle;
+        if write {
+            if !is_writable(file) {
+                error!("fuse: encountered write flag {:b}", flags);
+                return Err(libc::EROFS);
+            }
+            self.write_handles.insert(fh, ino);
+        } else {
+            let reader = match file.open_ro() {
                 Ok(v) => v,
                 Err(err) => {
-                    error!("fuse: could not read symlink: {}", err);
-                    reply.error(err.errno());
-                    return;
+                    error!("fuse: could not read inode {}: {}", ino, err);
+                    return Err(libc::EIO);
                 }
             };
-            reply.data(path.as_os_str().as_bytes());
-        } else {
-            reply.error(libc::ENOENT);
+            self.read_handles.insert(fh, reader);
         }
+        self.next_file_handle += 1;
+        OPEN_FILES.fetch_add(1, Ordering::Relaxed);
+        Ok(fh)
     }
 
-    fn open(&mut self, _req: &fuse::Request, ino: u64, flags: u32, reply: fuse::ReplyOpen) {
-        trace!("fuse open: {}, {:b}", ino, flags);
+    pub fn write_file(&mut self, ino: u64, fh: u64, data: &[u8]) -> Result<u32, i32> {
+        if self.write_handles.get(&fh) != Some(&ino) {
+            error!("fuse: no such open write handle, {}, inode {}", fh, ino);
+            return Err(libc::EBADF);
+        }
+        let node = &self.nodes.get(&ino).ok_or(libc::ENOENT)?.node;
+        let file = node.file().ok_or(libc::EISDIR)?;
+        file.write(data).map_err(|err| {
+            error!("fuse: could not write to inode {}: {}", ino, err);
+            err.errno()
+        })?;
+        Ok(data.len() as u32)
+    }
 
-        const WRITE_FLAGS: i32 = libc::O_APPEND | libc::O_CREAT | libc::O_EXCL | libc::O_TRUNC;
-        if flags & WRITE_FLAGS as u32 != 0 {
-            error!("fuse: encountered write flag {:b}", flags);
-            reply.error(libc::EROFS);
-            return;
+    /// Changing attributes is accepted but ignored for writable files, so they can be truncated
+    /// before writing to them.
+    pub fn set_attr(&mut self, ino: u64) -> Result<fuse::FileAttr, i32> {
+        let node = &self.nodes.get(&ino).ok_or(libc::ENOENT)?.node;
+        match node.file() {
+            Some(file) if is_writable(file) => self.attr(ino),
+            _ => Err(libc::EROFS),
         }
+    }
 
-        let node = match self.nodes.get(&ino) {
-            Some(v) => v,
+    pub fn read_file(&mut self, ino: u64, fh: u64, offset: i64, size: u32) -> Result<Vec<u8>, i32> {
+        let reader = match self.read_handles.get_mut(&fh) {
+            Some(e) => e,
             None => {
-                error!("fuse: no such inode: {}", ino);
-                reply.error(libc::ENOENT);
-                return;
+                error!("fuse: no such open read handle, {}, inode {}", fh, ino);
+                return Err(libc::EBADF);
             }
         };
-        let file = node.file().expect("can not call open on a non-file");
-        let reader = match file.open_ro() {
+
+        if let Err(err) = reader.seek(io::SeekFrom::Start(offset as u64)) {
+            error!("fuse: {}", err);
+            return Err(libc::EIO);
+        }
+        trace!("seek to {} ok", offset);
+        let mut buf = vec![0; size as usize];
+        let nread = match reader.read(&mut buf[..]) {
             Ok(v) => v,
             Err(err) => {
-                error!("fuse: could not read inode {}: {}", ino, err);
-                reply.error(libc::EIO);
-                return;
+                error!("fuse: {}", err);
+                return Err(libc::EIO);
+            }
+        };
+        trace!("read {} bytes ok", nread);
+        buf.truncate(nread);
+        Ok(buf)
+    }
+
+    pub fn release_file(&mut self, fh: u64) {
+        let reader = self.read_handles.remove(&fh);
+        let writer = self.write_handles.remove(&fh);
+        if reader.is_some() || writer.is_some() {
+            OPEN_FILES.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn open_dir(&mut self, ino: u64) -> Result<u64, i32> {
+        let children = {
+            let node = match self.nodes.get(&ino) {
+                Some(v) => &v.node,
+                None => {
+                    error!("fuse: no entry for inode {}", ino);
+                    return Err(libc::ENOENT);
+                }
+            };
+            let dir = node.directory().ok_or(libc::ENOTDIR)?;
+            match dir.files() {
+                Ok(v) => v,
+                Err(err) => {
+                    error!("fuse: could not get children for inode {}: {}", ino, err);
+                    return Err(libc::EIO);
+                }
             }
         };
+        // The inode numbers are held by the handle so they stay stable until it is released.
+        let entries = children
+            .into_iter()
+            .map(|(name, entry)| {
+                let key = InodeKey::for_child(ino, &name, &entry);
+                let child_ino = self.inodes.acquire(key);
+                self.inodes.link(child_ino, ino, &name);
+                (name, entry, child_ino)
+            })
+            .collect();
 
-        let fh = self.next_read_handle;
-        self.next_read_handle += 1;
-        self.read_handles.insert(fh, reader);
-        reply.opened(fh, flags);
+        let fh = self.next_readdir_handle;
+        self.next_readdir_handle += 1;
+        self.readdir_handles.insert(fh, entries);
+        OPEN_DIRS.fetch_add(1, Ordering::Relaxed);
+        Ok(fh)
+    }
+
+    /// Lists the entries of an open directory handle, starting at the specified offset.
+    pub fn read_dir(
+        &self,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+    ) -> Result<impl Iterator<Item = DirEntry> + '_, i32> {
+        let entries = match self.readdir_handles.get(&fh) {
+            Some(e) => e,
+            None => {
+                error!(
+                    "fuse: no open readdir handle for handle {}, inode {}",
+                    fh, ino
+                );
+                return Err(libc::EBADF);
+            }
+        };
+
+        let iter =
+            entries
+                .iter()
+                .skip(offset as usize)
+                .enumerate()
+                .map(move |(i, (name, node, ino))| DirEntry {
+                    ino: *ino,
+                    offset: offset + i as i64 + 1,
+                    kind: filetype_for_node(node),
+                    name: name.clone(),
+                });
+        Ok(iter)
+    }
+
+    pub fn release_dir(&mut self, fh: u64) {
+        let entries = match self.readdir_handles.remove(&fh) {
+            Some(v) => v,
+            None => return,
+        };
+        OPEN_DIRS.fetch_sub(1, Ordering::Relaxed);
+        for (_, _, ino) in entries {
+            self.inodes.release(ino);
+        }
+    }
+
+    pub fn get_xattr(&self, ino: u64, name: &str) -> Result<Vec<u8>, i32> {
+        self.list_xattrs(ino)?
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value)
+            .ok_or(libc::ENODATA)
+    }
+
+    /// Lists the names of the extended attributes of a node, each terminated by a NUL byte.
+    pub fn list_xattr(&self, ino: u64) -> Result<Vec<u8>, i32> {
+        let names = self
+            .list_xattrs(ino)?
+            .into_iter()
+            .flat_map(|(name, _)| name.into_bytes().into_iter().chain(Some(0)))
+            .collect();
+        Ok(names)
+    }
+
+    fn list_xattrs(&self, ino: u64) -> Result<Vec<(String, Vec<u8>)>, i32> {
+        let node = &self.nodes.get(&ino).ok_or(libc::ENOENT)?.node;
+        node.xattrs().map_err(|err| {
+            error!("fuse: could not get xattrs for {}: {}", ino, err);
+            err.errno()
+        })
+    }
+
+    /// Summarizes the nodes that are currently known. The sizes are those last reported by
+    /// `lookup` or `getattr`, so no node is queried.
+    pub fn stat_fs(&self) -> StatFs {
+        let bytes: u64 = self.nodes.values().map(|e| e.size).sum();
+        StatFs {
+            blocks: bytes.div_ceil(BLOCK_SIZE),
+            files: self.nodes.len() as u64,
+            block_size: BLOCK_SIZE as u32,
+            name_max: NAME_MAX,
+        }
+    }
+}
+
+impl<N> fuse::Filesystem for FS<N>
+where
+    N: NodeType,
+{
+    fn init(&mut self, _req: &fuse::Request) -> Result<(), os::raw::c_int> {
+        trace!("fuse init");
+        Ok(())
+    }
+
+    fn destroy(&mut self, _req: &fuse::Request) {
+        trace!("fuse destroy");
+    }
+
+    fn lookup(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        reply: fuse::ReplyEntry,
+    ) {
+        let name = os_name.to_string_lossy();
+        trace!("fuse lookup, {}, {}", parent_ino, name);
+
+        match self.lookup_child(parent_ino, &name) {
+            Ok(attrs) => {
+                let now = time::now().to_timespec();
+                reply.entry(&now, &attrs, 0);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn forget(&mut self, _req: &fuse::Request, ino: u64, nlookup: u64) {
+        trace!("fuse forget: {}, {}", ino, nlookup);
+        self.forget_node(ino, nlookup);
+    }
+
+    fn getattr(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyAttr) {
+        trace!("fuse getattr: {}", ino);
+
+        match self.attr(ino) {
+            Ok(attrs) => {
+                let ttl = (time::now() + time::Duration::seconds(30)).to_timespec();
+                reply.attr(&ttl, &attrs);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        _req: &fuse::Request,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<time::Timespec>,
+        _mtime: Option<time::Timespec>,
+        _fh: Option<u64>,
+        _crtime: Option<time::Timespec>,
+        _chgtime: Option<time::Timespec>,
+        _bkuptime: Option<time::Timespec>,
+        _flags: Option<u32>,
+        reply: fuse::ReplyAttr,
+    ) {
+        trace!("fuse setattr: {}, size={:?}", ino, size);
+
+        match self.set_attr(ino) {
+            Ok(attrs) => {
+                let ttl = (time::now() + time::Duration::seconds(30)).to_timespec();
+                reply.attr(&ttl, &attrs);
+            }
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readlink(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyData) {
+        trace!("fuse readlink: ino={}", ino);
+
+        match self.read_link(ino) {
+            Ok(path) => reply.data(path.as_os_str().as_bytes()),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn open(&mut self, _req: &fuse::Request, ino: u64, flags: u32, reply: fuse::ReplyOpen) {
+        trace!("fuse open: {}, {:b}", ino, flags);
+
+        match self.open_file(ino, flags) {
+            Ok(fh) => reply.opened(fh, flags),
+            Err(errno) => reply.error(errno),
+        }
     }
 
     fn read(
@@ -209,32 +609,34 @@ where
             size
         );
 
-        let reader = match self.read_handles.get_mut(&fh) {
-            Some(e) => e,
-            None => {
-                error!("fuse: no such open read handle, {}, inode {}", fh, ino);
-                reply.error(libc::EBADF);
-                return;
-            }
-        };
+        match self.read_file(ino, fh, offset, size) {
+            Ok(buf) => reply.data(&buf),
+            Err(errno) => reply.error(errno),
+        }
+    }
 
-        if let Err(err) = reader.seek(io::SeekFrom::Start(offset as u64)) {
-            error!("fuse: {}", err);
-            reply.error(libc::EIO);
-            return;
+    fn write(
+        &mut self,
+        _req: &fuse::Request,
+        ino: u64,
+        fh: u64,
+        offset: i64,
+        data: &[u8],
+        _flags: u32,
+        reply: fuse::ReplyWrite,
+    ) {
+        trace!(
+            "fuse write: ino={}, fh={}, offset={}, size={}",
+            ino,
+            fh,
+            offset,
+            data.len()
+        );
+
+        match self.write_file(ino, fh, data) {
+            Ok(written) => reply.written(written),
+            Err(errno) => reply.error(errno),
         }
-        trace!("seek to {} ok", offset);
-        let mut buf = vec![0; size as usize];
-        let nread = match reader.read(&mut buf[..]) {
-            Ok(v) => v,
-            Err(err) => {
-                error!("fuse: {}", err);
-                reply.error(libc::EIO);
-                return;
-            }
-        };
-        trace!("read {} bytes ok", nread);
-        reply.data(&buf[..nread]);
     }
 
     fn release(
@@ -256,7 +658,7 @@ where
             flush
         );
 
-        self.read_handles.remove(&fh);
+        self.release_file(fh);
         reply.ok();
     }
 
@@ -269,94 +671,174 @@ where
     ) {
         trace!("fuse opendir: {}, {}", parent_ino, flags);
 
-        let children = {
-            let node = match self.nodes.get(&parent_ino) {
-                Some(v) => v,
-                None => {
-                    error!("fuse: no entry for inode {}", parent_ino);
-                    reply.error(libc::ENOENT);
-                    return;
-                }
-            };
-            let dir = node
-                .directory()
-                .expect("can not call lookup on a non-directory");
-            match dir.files() {
-                Ok(v) => v,
-                Err(err) => {
-                    error!(
-                        "fuse: could not get children for inode {}: {}",
-                        parent_ino, err
-                    );
-                    reply.error(libc::EIO);
-                    return;
-                }
+        match self.open_dir(parent_ino) {
+            Ok(fh) => reply.opened(fh, flags),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        fh: u64,
+        offset: i64,
+        mut reply: fuse::ReplyDirectory,
+    ) {
+        trace!("fuse readdir: {}, {}, {}", parent_ino, fh, offset);
+
+        let entries = match self.read_dir(parent_ino, fh, offset) {
+            Ok(v) => v,
+            Err(errno) => {
+                reply.error(errno);
+                return;
             }
         };
-        let entries = children
-            .into_iter()
-            .map(|(name, entry)| {
-                let ino = inode_for_child(parent_ino, &name);
-                (name, entry, ino)
-            })
-            .collect();
+        for entry in entries {
+            trace!(
+                "fuse readdir node: {} {:?}, {}",
+                entry.ino,
+                entry.kind,
+                entry.name
+            );
+            if reply.add(entry.ino, entry.offset, entry.kind, &entry.name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn releasedir(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        fh: u64,
+        flags: u32,
+        reply: fuse::ReplyEmpty,
+    ) {
+        trace!("fuse releasedir: {}, {}, {}", parent_ino, fh, flags);
+
+        self.release_dir(fh);
+        reply.ok();
+    }
+
+    fn access(&mut self, _req: &fuse::Request, ino: u64, mask: u32, reply: fuse::ReplyEmpty) {
+        trace!("fuse access: {}, {}", ino, mask);
+        reply.ok();
+    }
+
+    fn getxattr(
+        &mut self,
+        _req: &fuse::Request,
+        ino: u64,
+        os_name: &ffi::OsStr,
+        size: u32,
+        reply: fuse::ReplyXattr,
+    ) {
+        let name = os_name.to_string_lossy();
+        trace!("fuse getxattr: {}, {}, {}", ino, name, size);
+
+        match self.get_xattr(ino, &name) {
+            Ok(value) => reply_xattr(reply, size, &value),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn listxattr(&mut self, _req: &fuse::Request, ino: u64, size: u32, reply: fuse::ReplyXattr) {
+        trace!("fuse listxattr: {}, {}", ino, size);
+
+        match self.list_xattr(ino) {
+            Ok(names) => reply_xattr(reply, size, &names),
+            Err(errno) => reply.error(errno),
+        }
+    }
+
+    fn statfs(&mut self, _req: &fuse::Request, ino: u64, reply: fuse::ReplyStatfs) {
+        trace!("fuse statfs: {}", ino);
+
+        let st = self.stat_fs();
+        reply.statfs(
+            st.blocks,
+            0,
+            0,
+            st.files,
+            0,
+            st.block_size,
+            st.name_max,
+            st.block_size,
+        );
+    }
+
+    fn unlink(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        reply: fuse::ReplyEmpty,
+    ) {
+        let name = os_name.to_string_lossy();
+        trace!("fuse unlink: {}, {}", parent_ino, name);
 
-        let fh = self.next_readdir_handle;
-        self.next_readdir_handle += 1;
-        self.readdir_handles.insert(fh, entries);
-        reply.opened(fh, flags);
+        match self.unlink_child(parent_ino, &name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
     }
 
-    fn readdir(
+    fn symlink(
         &mut self,
         _req: &fuse::Request,
         parent_ino: u64,
-        fh: u64,
-        offset: i64,
-        mut reply: fuse::ReplyDirectory,
+        os_name: &ffi::OsStr,
+        link: &Path,
+        reply: fuse::ReplyEntry,
     ) {
-        trace!("fuse readdir: {}, {}, {}", parent_ino, fh, offset);
+        let name = os_name.to_string_lossy();
+        trace!("fuse symlink: {}, {}, {:?}", parent_ino, name, link);
 
-        let entries = match self.readdir_handles.get(&fh) {
-            Some(e) => e,
-            None => {
-                error!(
-                    "fuse: no open readdir handle for handle {}, inode {}",
-                    fh, parent_ino
-                );
-                reply.error(libc::EBADF);
-                return;
+        match self.make_symlink(parent_ino, &name, link) {
+            Ok(attrs) => {
+                let now = time::now().to_timespec();
+                reply.entry(&now, &attrs, 0);
             }
-        };
+            Err(errno) => reply.error(errno),
+        }
+    }
 
-        let iter = entries.iter().skip(offset as usize).enumerate();
-        for (i, (name, node, ino)) in iter {
-            let typ = filetype_for_node(&node);
-            trace!("fuse readdir node: {} {:?}, {}", ino, typ, name);
-            if reply.add(*ino, offset + i as i64 + 1, typ, name) {
-                break;
+    fn mkdir(
+        &mut self,
+        _req: &fuse::Request,
+        parent_ino: u64,
+        os_name: &ffi::OsStr,
+        mode: u32,
+        reply: fuse::ReplyEntry,
+    ) {
+        let name = os_name.to_string_lossy();
+        trace!("fuse mkdir: {}, {}, {:o}", parent_ino, name, mode);
+
+        match self.make_dir(parent_ino, &name) {
+            Ok(attrs) => {
+                let now = time::now().to_timespec();
+                reply.entry(&now, &attrs, 0);
             }
+            Err(errno) => reply.error(errno),
         }
-        reply.ok();
     }
 
-    fn releasedir(
+    fn rmdir(
         &mut self,
         _req: &fuse::Request,
         parent_ino: u64,
-        fh: u64,
-        flags: u32,
+        os_name: &ffi::OsStr,
         reply: fuse::ReplyEmpty,
     ) {
-        trace!("fuse releasedir: {}, {}, {}", parent_ino, fh, flags);
-
-        self.readdir_handles.remove(&fh);
-        reply.ok();
-    }
+        let name = os_name.to_string_lossy();
+        trace!("fuse rmdir: {}, {}", parent_ino, name);
 
-    fn access(&mut self, _req: &fuse::Request, ino: u64, mask: u32, reply: fuse::ReplyEmpty) {
-        trace!("fuse access: {}, {}", ino, mask);
-        reply.ok();
+        match self.remove_dir(parent_ino, &name) {
+            Ok(()) => reply.ok(),
+            Err(errno) => reply.error(errno),
+        }
     }
 
     //    fn getlk(
@@ -388,40 +870,6 @@ where
     //    ) {
     //        unimplemented!();
     //    }
-    //    fn statfs(&mut self, _req: &fuse::Request, ino: u64, _reply: fuse::ReplyStatfs) {
-    //    }
-    //    fn getxattr(
-    //        &mut self,
-    //        _req: &fuse::Request,
-    //        _ino: u64,
-    //        _os_name: &ffi::OsStr,
-    //        _size: u32,
-    //        reply: fuse::ReplyXattr,
-    //    ) {
-    //        unimplemented!();
-    //    }
-    //
-    //    fn listxattr(&mut self, _req: &fuse::Request, _ino: u64, _size: u32, _reply: fuse::ReplyXattr) {
-    //        unimplemented!();
-    //    }
-    //    fn forget(&mut self, _req: &Request, _ino: u64, _nlookup: u64) { ... }
-    //    fn setattr(
-    //        &mut self,
-    //        _req: &Request,
-    //        _ino: u64,
-    //        _mode: Option<u32>,
-    //        _uid: Option<u32>,
-    //        _gid: Option<u32>,
-    //        _size: Option<u64>,
-    //        _atime: Option<Timespec>,
-    //        _mtime: Option<Timespec>,
-    //        _fh: Option<u64>,
-    //        _crtime: Option<Timespec>,
-    //        _chgtime: Option<Timespec>,
-    //        _bkuptime: Option<Timespec>,
-    //        _flags: Option<u32>,
-    //        reply: ReplyAttr
-    //    ) { ... }
     //    fn mknod(
     //        &mut self,
     //        _req: &Request,
@@ -431,36 +879,6 @@ where
     //        _rdev: u32,
     //        reply: ReplyEntry
     //    ) { ... }
-    //    fn mkdir(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _mode: u32,
-    //        reply: ReplyEntry
-    //    ) { ... }
-    //    fn unlink(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        reply: ReplyEmpty
-    //    ) { ... }
-    //    fn rmdir(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        reply: ReplyEmpty
-    //    ) { ... }
-    //    fn symlink(
-    //        &mut self,
-    //        _req: &Request,
-    //        _parent: u64,
-    //        _name: &OsStr,
-    //        _link: &Path,
-    //        reply: ReplyEntry
-    //    ) { ... }
     //    fn rename(
     //        &mut self,
     //        _req: &Request,
@@ -478,16 +896,6 @@ where
     //        _newname: &OsStr,
     //        reply: ReplyEntry
     //    ) { ... }
-    //    fn write(
-    //        &mut self,
-    //        _req: &Request,
-    //        _ino: u64,
-    //        _fh: u64,
-    //        _offset: i64,
-    //        _data: &[u8],
-    //        _flags: u32,
-    //        reply: ReplyWrite
-    //    ) { ... }
     //    fn flush(
     //        &mut self,
     //        _req: &Request,
@@ -548,11 +956,20 @@ where
     //    ) { }
 }
 
-fn inode_for_child(parent_ino: u64, name: &str) -> u64 {
-    let mut s = DefaultHasher::new();
-    parent_ino.hash(&mut s);
-    name.hash(&mut s);
-    s.finish()
+/// Replies with either the size of the data if the caller is probing for it, or the data itself if
+/// it fits in the caller's buffer.
+fn reply_xattr(reply: fuse::ReplyXattr, size: u32, data: &[u8]) {
+    if size == 0 {
+        reply.size(data.len() as u32);
+    } else if data.len() > size as usize {
+        reply.error(libc::ERANGE);
+    } else {
+        reply.data(data);
+    }
+}
+
+fn is_writable<F: File>(file: &F) -> bool {
+    file.metadata().is_ok_and(|meta| meta.perm & 0o222 != 0)
 }
 
 fn filetype_for_node<N: NodeType>(node: &Node<N>) -> fuse::FileType {
@@ -573,8 +990,6 @@ fn attrs_for_file<N: NodeType>(
     uid: u32,
     gid: u32,
 ) -> Result<fuse::FileAttr, N::Error> {
-    const BLOCK_SIZE: u64 = 1024;
-
     let meta = node.metadata()?;
     let size = match node {
         Node::File(f) => f.size()?,
@@ -597,3 +1012,528 @@ fn attrs_for_file<N: NodeType>(
         flags: 0,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error;
+    use std::fmt;
+    use std::path::Path;
+
+    #[derive(Debug)]
+    struct TestError(i32);
+
+    impl Error for TestError {
+        fn not_found() -> Self {
+            TestError(libc::ENOENT)
+        }
+
+        fn read_only() -> Self {
+            TestError(libc::EROFS)
+        }
+
+        fn errno(&self) -> i32 {
+            self.0
+        }
+    }
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{:?}", self)
+        }
+    }
+
+    impl error::Error for TestError {}
+
+    fn test_meta() -> Metadata {
+        Metadata {
+            mtime: Utc::now(),
+            ctime: Utc::now(),
+            perm: 0o444,
+        }
+    }
+
+    struct TestRoot;
+
+    impl NodeType for TestRoot {
+        type Error = TestError;
+        type File = TestFile;
+        type Directory = TestDir;
+        type Symlink = TestLink;
+
+        fn root(&self) -> Self::Directory {
+            TestDir::Root
+        }
+    }
+
+    enum TestDir {
+        Root,
+        Sub,
+    }
+
+    impl Meta for TestDir {
+        type Error = TestError;
+        fn metadata(&self) -> Result<Metadata, Self::Error> {
+            Ok(test_meta())
+        }
+
+        fn identity(&self) -> Option<Identity> {
+            Some(Identity {
+                kind: "test_dir",
+                id: 0,
+            })
+        }
+    }
+
+    impl Directory<TestRoot> for TestDir {
+        fn files(&self) -> Result<Vec<(String, Node<TestRoot>)>, Self::Error> {
+            match self {
+                TestDir::Root => Ok(vec![
+                    (
+                        "a.txt".to_string(),
+                        Node::File(TestFile(Some(b"hello"), None, false)),
+                    ),
+                    ("sub".to_string(), Node::Directory(TestDir::Sub)),
+                    ("link".to_string(), Node::Symlink(TestLink)),
+                    (
+                        "broken.txt".to_string(),
+                        Node::File(TestFile(None, None, false)),
+                    ),
+                    (
+                        "shared.txt".to_string(),
+                        Node::File(TestFile(Some(b"shared"), Some(7), false)),
+                    ),
+                ]),
+                TestDir::Sub => Ok(vec![
+                    (
+                        "a.txt".to_string(),
+                        Node::File(TestFile(Some(b"other"), None, false)),
+                    ),
+                    (
+                        "same.txt".to_string(),
+                        Node::File(TestFile(Some(b"shared"), Some(7), false)),
+                    ),
+                    ("w.txt".to_string(), Node::File(TestFile(None, None, true))),
+                ]),
+            }
+        }
+
+        // Only the subdirectory can be changed, and only a.txt, same.txt and dir can be removed
+        // from it.
+        fn symlink(&self, _name: &str, _target: &Path) -> Result<Node<TestRoot>, Self::Error> {
+            match self {
+                TestDir::Root => Err(TestError::read_only()),
+                TestDir::Sub => Ok(Node::Symlink(TestLink)),
+            }
+        }
+
+        fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+            match (self, name) {
+                (TestDir::Root, _) => Err(TestError::read_only()),
+                (TestDir::Sub, "a.txt") | (TestDir::Sub, "same.txt") => Ok(()),
+                (TestDir::Sub, _) => Err(TestError::not_found()),
+            }
+        }
+
+        fn mkdir(&self, _name: &str) -> Result<Node<TestRoot>, Self::Error> {
+            match self {
+                TestDir::Root => Err(TestError::read_only()),
+                TestDir::Sub => Ok(Node::Directory(TestDir::Sub)),
+            }
+        }
+
+        fn rmdir(&self, name: &str) -> Result<(), Self::Error> {
+            match (self, name) {
+                (TestDir::Root, _) => Err(TestError::read_only()),
+                (TestDir::Sub, "dir") => Ok(()),
+                (TestDir::Sub, _) => Err(TestError::not_found()),
+            }
+        }
+    }
+
+    /// A file with fixed contents, or one that fails to open if there are none. Files with an id
+    /// share their identity. Writable files accept any write but "bad".
+    struct TestFile(Option<&'static [u8]>, Option<i64>, bool);
+
+    impl Meta for TestFile {
+        type Error = TestError;
+        fn metadata(&self) -> Result<Metadata, Self::Error> {
+            let perm = if self.2 { 0o644 } else { 0o444 };
+            Ok(Metadata {
+                perm,
+                ..test_meta()
+            })
+        }
+
+        fn identity(&self) -> Option<Identity> {
+            self.1.map(|id| Identity { kind: "test", id })
+        }
+
+        fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+            Ok(vec![
+                ("user.test.a".to_string(), b"1".to_vec()),
+                ("user.test.b".to_string(), b"22".to_vec()),
+            ])
+        }
+    }
+
+    impl File for TestFile {
+        type Reader = io::Cursor<&'static [u8]>;
+
+        fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+            self.0.map(io::Cursor::new).ok_or(TestError(libc::EACCES))
+        }
+
+        fn size(&self) -> Result<u64, Self::Error> {
+            Ok(self.0.map(|b| b.len() as u64).unwrap_or(0))
+        }
+
+        fn write(&self, data: &[u8]) -> Result<(), Self::Error> {
+            match (self.2, data) {
+                (false, _) => Err(TestError::read_only()),
+                (true, b"bad") => Err(TestError(libc::EINVAL)),
+                (true, _) => Ok(()),
+            }
+        }
+    }
+
+    struct TestLink;
+
+    impl Meta for TestLink {
+        type Error = TestError;
+        fn metadata(&self) -> Result<Metadata, Self::Error> {
+            Ok(test_meta())
+        }
+    }
+
+    impl Symlink for TestLink {
+        fn read_link(&self) -> Result<PathBuf, Self::Error> {
+            Ok(PathBuf::from("sub"))
+        }
+    }
+
+    fn test_fs() -> FS<TestRoot> {
+        FS::new(&TestRoot, 1000, 1000)
+    }
+
+    #[test]
+    fn lookup_attrs() {
+        let mut fs = test_fs();
+
+        let attrs = fs.lookup_child(INO_ROOT, "a.txt").unwrap();
+        assert_eq!(fuse::FileType::RegularFile, attrs.kind);
+        assert_eq!(5, attrs.size);
+        assert_eq!(1000, attrs.uid);
+
+        let attrs2 = fs.attr(attrs.ino).unwrap();
+        assert_eq!(attrs.ino, attrs2.ino);
+        assert_eq!(attrs.size, attrs2.size);
+
+        let attrs = fs.lookup_child(INO_ROOT, "sub").unwrap();
+        assert_eq!(fuse::FileType::Directory, attrs.kind);
+    }
+
+    #[test]
+    fn lookup_inode_allocation() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(a, fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino);
+        assert_ne!(INO_ROOT, a);
+
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        let sub_a = fs.lookup_child(sub, "a.txt").unwrap().ino;
+        assert_ne!(a, sub_a);
+    }
+
+    #[test]
+    fn lookup_identity() {
+        let mut fs = test_fs();
+
+        // A node that is reachable through different paths has one inode and is hard linked.
+        let shared = fs.lookup_child(INO_ROOT, "shared.txt").unwrap();
+        assert_eq!(1, shared.nlink);
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        let same = fs.lookup_child(sub, "same.txt").unwrap();
+        assert_eq!(shared.ino, same.ino);
+        assert_eq!(2, same.nlink);
+        assert_eq!(3, fs.stat_fs().files);
+
+        // The node stays until every lookup has been forgotten.
+        fs.forget_node(shared.ino, 1);
+        assert!(fs.attr(shared.ino).is_ok());
+        fs.forget_node(shared.ino, 1);
+        assert!(fs.attr(shared.ino).is_err());
+    }
+
+    #[test]
+    fn unlink_drops_links() {
+        let mut fs = test_fs();
+
+        let shared = fs.lookup_child(INO_ROOT, "shared.txt").unwrap().ino;
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        fs.lookup_child(sub, "same.txt").unwrap();
+        assert_eq!(2, fs.attr(shared).unwrap().nlink);
+        assert_eq!(Ok(()), fs.unlink_child(sub, "same.txt"));
+        assert_eq!(1, fs.attr(shared).unwrap().nlink);
+
+        // Links from a directory that has been evicted are gone along with it, so looking the
+        // directory up again does not add to the count.
+        fs.lookup_child(sub, "same.txt").unwrap();
+        fs.forget_node(sub, 1);
+        assert_eq!(1, fs.attr(shared).unwrap().nlink);
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        fs.lookup_child(sub, "same.txt").unwrap();
+        assert_eq!(2, fs.attr(shared).unwrap().nlink);
+    }
+
+    #[test]
+    fn readdir_pins_inodes() {
+        let mut fs = test_fs();
+
+        let fh = fs.open_dir(INO_ROOT).unwrap();
+        let a = fs.read_dir(INO_ROOT, fh, 0).unwrap().next().unwrap().ino;
+
+        // The inode number of a listed entry stays reserved after the entry has been forgotten.
+        fs.lookup_child(INO_ROOT, "a.txt").unwrap();
+        fs.forget_node(a, 1);
+        assert_eq!(a, fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino);
+        fs.forget_node(a, 1);
+
+        // Once the handle is released, it is freed.
+        fs.release_dir(fh);
+        assert_ne!(a, fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino);
+    }
+
+    #[test]
+    fn lookup_errors() {
+        let mut fs = test_fs();
+
+        assert_eq!(
+            Err(libc::ENOENT),
+            fs.lookup_child(INO_ROOT, "nope").map(|a| a.ino)
+        );
+        assert_eq!(
+            Err(libc::ENOENT),
+            fs.lookup_child(1234, "a.txt").map(|a| a.ino)
+        );
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(Err(libc::ENOTDIR), fs.lookup_child(a, "b").map(|a| a.ino));
+        assert_eq!(Err(libc::ENOENT), fs.attr(1234).map(|a| a.ino));
+    }
+
+    #[test]
+    fn readdir_offsets() {
+        let mut fs = test_fs();
+
+        let fh = fs.open_dir(INO_ROOT).unwrap();
+        let entries: Vec<_> = fs.read_dir(INO_ROOT, fh, 0).unwrap().collect();
+        let names: Vec<_> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(
+            vec!["a.txt", "sub", "link", "broken.txt", "shared.txt"],
+            names
+        );
+        let offsets: Vec<_> = entries.iter().map(|e| e.offset).collect();
+        assert_eq!(vec![1, 2, 3, 4, 5], offsets);
+        assert_eq!(fuse::FileType::Symlink, entries[2].kind);
+
+        // Continuing from an entry's offset yields the entries after it.
+        let rest: Vec<_> = fs
+            .read_dir(INO_ROOT, fh, entries[1].offset)
+            .unwrap()
+            .collect();
+        assert_eq!(&entries[2..], &rest[..]);
+
+        // The inode numbers in a listing match those handed out by lookup.
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(a, entries[0].ino);
+    }
+
+    #[test]
+    fn readdir_handle_lifetime() {
+        let mut fs = test_fs();
+
+        let fh1 = fs.open_dir(INO_ROOT).unwrap();
+        let fh2 = fs.open_dir(INO_ROOT).unwrap();
+        assert_ne!(fh1, fh2);
+
+        fs.release_dir(fh1);
+        assert_eq!(Err(libc::EBADF), fs.read_dir(INO_ROOT, fh1, 0).map(|_| ()));
+        assert_eq!(5, fs.read_dir(INO_ROOT, fh2, 0).unwrap().count());
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(Err(libc::ENOTDIR), fs.open_dir(a));
+    }
+
+    #[test]
+    fn open_read_release() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        let fh = fs.open_file(a, libc::O_RDONLY as u32).unwrap();
+        assert_eq!(b"ell".to_vec(), fs.read_file(a, fh, 1, 3).unwrap());
+        assert_eq!(b"lo".to_vec(), fs.read_file(a, fh, 3, 16).unwrap());
+        assert_eq!(b"".to_vec(), fs.read_file(a, fh, 5, 16).unwrap());
+
+        fs.release_file(fh);
+        assert_eq!(Err(libc::EBADF), fs.read_file(a, fh, 0, 16));
+    }
+
+    #[test]
+    fn open_errors() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(Err(libc::EROFS), fs.open_file(a, libc::O_TRUNC as u32));
+        assert_eq!(Err(libc::EISDIR), fs.open_file(INO_ROOT, 0));
+        assert_eq!(Err(libc::ENOENT), fs.open_file(1234, 0));
+
+        let broken = fs.lookup_child(INO_ROOT, "broken.txt").unwrap().ino;
+        assert_eq!(Err(libc::EIO), fs.open_file(broken, 0));
+    }
+
+    #[test]
+    fn open_write_release() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(Err(libc::EROFS), fs.open_file(a, libc::O_WRONLY as u32));
+        assert_eq!(Err(libc::EROFS), fs.set_attr(a).map(|attrs| attrs.ino));
+
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        let w = fs.lookup_child(sub, "w.txt").unwrap().ino;
+        assert_eq!(w, fs.set_attr(w).unwrap().ino);
+        let fh = fs
+            .open_file(w, (libc::O_WRONLY | libc::O_APPEND) as u32)
+            .unwrap();
+        assert_eq!(Ok(5), fs.write_file(w, fh, b"hello"));
+        assert_eq!(Err(libc::EINVAL), fs.write_file(w, fh, b"bad"));
+        assert_eq!(Err(libc::EBADF), fs.read_file(w, fh, 0, 5));
+        fs.release_file(fh);
+        assert_eq!(Err(libc::EBADF), fs.write_file(w, fh, b"hello"));
+
+        // Handles opened for reading can not be written to.
+        let fh = fs.open_file(a, 0).unwrap();
+        assert_eq!(Err(libc::EBADF), fs.write_file(a, fh, b"hello"));
+    }
+
+    #[test]
+    fn statfs() {
+        let mut fs = test_fs();
+
+        let st = fs.stat_fs();
+        assert_eq!(0, st.blocks);
+        assert_eq!(1, st.files);
+
+        fs.lookup_child(INO_ROOT, "a.txt").unwrap();
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        fs.lookup_child(sub, "a.txt").unwrap();
+        // Looking up the same node twice does not count it twice.
+        fs.lookup_child(INO_ROOT, "a.txt").unwrap();
+
+        let st = fs.stat_fs();
+        assert_eq!(1, st.blocks);
+        assert_eq!(4, st.files);
+        assert_eq!(NAME_MAX, st.name_max);
+    }
+
+    #[test]
+    fn xattrs() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(
+            b"user.test.a\0user.test.b\0".to_vec(),
+            fs.list_xattr(a).unwrap()
+        );
+        assert_eq!(Ok(b"22".to_vec()), fs.get_xattr(a, "user.test.b"));
+        assert_eq!(Err(libc::ENODATA), fs.get_xattr(a, "user.test.c"));
+
+        // Nodes without extended attributes have an empty list.
+        assert_eq!(Ok(Vec::new()), fs.list_xattr(INO_ROOT));
+        assert_eq!(Err(libc::ENOENT), fs.list_xattr(1234));
+    }
+
+    #[test]
+    fn forget_evicts() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        fs.lookup_child(INO_ROOT, "a.txt").unwrap();
+        assert_eq!(2, fs.stat_fs().files);
+
+        fs.forget_node(a, 1);
+        assert!(fs.attr(a).is_ok());
+        fs.forget_node(a, 1);
+        assert_eq!(Err(libc::ENOENT), fs.attr(a).map(|a| a.ino));
+        assert_eq!(1, fs.stat_fs().files);
+
+        // The node can be looked up again afterwards.
+        assert!(fs.lookup_child(INO_ROOT, "a.txt").is_ok());
+        assert_eq!(2, fs.stat_fs().files);
+    }
+
+    #[test]
+    fn forget_root() {
+        let mut fs = test_fs();
+
+        fs.forget_node(INO_ROOT, 1);
+        assert!(fs.attr(INO_ROOT).is_ok());
+        assert!(fs.lookup_child(INO_ROOT, "a.txt").is_ok());
+    }
+
+    #[test]
+    fn forget_open_file() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        let fh = fs.open_file(a, 0).unwrap();
+        fs.forget_node(a, 1);
+        assert_eq!(b"hello".to_vec(), fs.read_file(a, fh, 0, 16).unwrap());
+    }
+
+    #[test]
+    fn readlink() {
+        let mut fs = test_fs();
+
+        let link = fs.lookup_child(INO_ROOT, "link").unwrap().ino;
+        assert_eq!(Ok(Path::new("sub").to_path_buf()), fs.read_link(link));
+        assert_eq!(Err(libc::EINVAL), fs.read_link(INO_ROOT));
+    }
+
+    #[test]
+    fn symlink_unlink() {
+        let mut fs = test_fs();
+
+        let a = fs.lookup_child(INO_ROOT, "a.txt").unwrap().ino;
+        assert_eq!(Err(libc::EROFS), fs.unlink_child(INO_ROOT, "a.txt"));
+        assert_eq!(Err(libc::ENOTDIR), fs.unlink_child(a, "a.txt"));
+        let err = fs.make_symlink(INO_ROOT, "new", Path::new("sub")).err();
+        assert_eq!(Some(libc::EROFS), err);
+
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        let link = fs.make_symlink(sub, "new", Path::new("../a.txt")).unwrap();
+        assert_eq!(fuse::FileType::Symlink, link.kind);
+        assert!(fs.attr(link.ino).is_ok());
+        assert_eq!(Ok(()), fs.unlink_child(sub, "a.txt"));
+        assert_eq!(Err(libc::ENOENT), fs.unlink_child(sub, "b.txt"));
+    }
+
+    #[test]
+    fn mkdir_rmdir() {
+        let mut fs = test_fs();
+
+        assert_eq!(Some(libc::EROFS), fs.make_dir(INO_ROOT, "dir").err());
+        assert_eq!(Err(libc::EROFS), fs.remove_dir(INO_ROOT, "sub"));
+
+        let sub = fs.lookup_child(INO_ROOT, "sub").unwrap().ino;
+        let dir = fs.make_dir(sub, "dir").unwrap();
+        assert_eq!(fuse::FileType::Directory, dir.kind);
+        // The new directory has the same identity as its parent, but directories are never linked.
+        assert_ne!(sub, dir.ino);
+        assert!(fs.open_dir(dir.ino).is_ok());
+        assert_eq!(Ok(()), fs.remove_dir(sub, "dir"));
+        assert_eq!(Err(libc::ENOENT), fs.remove_dir(sub, "other"));
+    }
+}
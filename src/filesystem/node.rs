@@ -2,10 +2,12 @@ use super::*;
 use chrono::{DateTime, Utc};
 use std::error;
 use std::iter::Iterator;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 pub trait Error: error::Error {
     fn not_found() -> Self;
+    /// The error for changes to nodes that can not be changed.
+    fn read_only() -> Self;
     fn errno(&self) -> i32;
 }
 
@@ -16,15 +18,39 @@ pub struct Metadata {
     pub perm: u16,
 }
 
+/// Identifies the object that a node represents, independently of the path through which it was
+/// reached. Nodes with the same identity share an inode number, except for directories which are
+/// always told apart by their path.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Identity {
+    pub kind: &'static str,
+    pub id: i64,
+}
+
 pub trait Meta {
     type Error: Error;
     fn metadata(&self) -> Result<Metadata, Self::Error>;
+
+    fn identity(&self) -> Option<Identity> {
+        None
+    }
+
+    /// Extended attributes as (name, value) pairs. Names should include a namespace, e.g. `user.`.
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(Vec::new())
+    }
 }
 
 pub trait File: Meta {
     type Reader: io::Read + io::Seek;
     fn open_ro(&self) -> Result<Self::Reader, Self::Error>;
     fn size(&self) -> Result<u64, Self::Error>;
+
+    /// Handles data written to the file. Only files with write permission are opened for writing.
+    /// The offset is not passed on, so every write should be complete by itself.
+    fn write(&self, _data: &[u8]) -> Result<(), Self::Error> {
+        Err(Self::Error::read_only())
+    }
 }
 
 pub trait Directory<N: NodeType>: Meta {
@@ -37,6 +63,27 @@ pub trait Directory<N: NodeType>: Meta {
             .map(|(_, entry)| entry)
             .ok_or_else(Self::Error::not_found)
     }
+
+    /// Creates a symbolic link in this directory that points to target and returns the node that
+    /// now goes by the name. Directories are read-only unless they implement this.
+    fn symlink(&self, _name: &str, _target: &Path) -> Result<Node<N>, Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Removes the entry with the specified name.
+    fn unlink(&self, _name: &str) -> Result<(), Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Creates a directory and returns its node.
+    fn mkdir(&self, _name: &str) -> Result<Node<N>, Self::Error> {
+        Err(Self::Error::read_only())
+    }
+
+    /// Removes the directory with the specified name.
+    fn rmdir(&self, _name: &str) -> Result<(), Self::Error> {
+        Err(Self::Error::read_only())
+    }
 }
 
 pub trait Symlink: Meta {
@@ -91,4 +138,20 @@ impl<T: NodeType> Meta for Node<T> {
             Node::Symlink(f) => f.metadata(),
         }
     }
+
+    fn identity(&self) -> Option<Identity> {
+        match self {
+            Node::File(f) => f.identity(),
+            Node::Directory(f) => f.identity(),
+            Node::Symlink(f) => f.identity(),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        match self {
+            Node::File(f) => f.xattrs(),
+            Node::Directory(f) => f.xattrs(),
+            Node::Symlink(f) => f.xattrs(),
+        }
+    }
 }
@@ -1,7 +1,15 @@
 use super::*;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 
+/// Incremented to make every DirCache forget its entries.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Makes every cached directory fetch its entries again when it is next used.
+pub fn invalidate_all() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
 #[derive(Clone)]
 pub struct CacheRoot<N>
 where
@@ -56,6 +64,8 @@ where
     cached_files: RefCell<Option<Vec<(String, Node<CacheRoot<N>>)>>>,
     hidden_cached_files: RefCell<HashMap<String, Node<CacheRoot<N>>>>,
     non_files: RefCell<HashSet<String>>,
+    /// The value of GENERATION at the time the entries were cached.
+    generation: Cell<u64>,
 }
 
 impl<N> DirCache<N>
@@ -71,6 +81,21 @@ where
             cached_files: RefCell::new(None),
             hidden_cached_files: RefCell::new(HashMap::new()),
             non_files: RefCell::new(HashSet::new()),
+            generation: Cell::new(GENERATION.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Forgets the entries of the directory so they are fetched again after it has changed.
+    pub fn invalidate(&self) {
+        self.cached_files.replace(None);
+        self.hidden_cached_files.borrow_mut().clear();
+        self.non_files.borrow_mut().clear();
+    }
+
+    fn invalidate_if_outdated(&self) {
+        let generation = GENERATION.load(Ordering::Relaxed);
+        if self.generation.replace(generation) != generation {
+            self.invalidate();
         }
     }
 }
@@ -86,6 +111,14 @@ where
     fn metadata(&self) -> Result<Metadata, Self::Error> {
         self.inner.metadata()
     }
+
+    fn identity(&self) -> Option<Identity> {
+        self.inner.identity()
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        self.inner.xattrs()
+    }
 }
 
 impl<N> Directory<CacheRoot<N>> for DirCache<N>
@@ -96,10 +129,13 @@ where
     N::Symlink: Clone,
 {
     fn files(&self) -> Result<Vec<(String, Node<CacheRoot<N>>)>, Self::Error> {
+        self.invalidate_if_outdated();
         let mut cached = self.cached_files.borrow_mut();
         if cached.is_some() {
+            DIR_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
             return Ok(cached.as_ref().unwrap().to_vec());
         }
+        DIR_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
         let files: Vec<_> = self
             .inner
             .files()?
@@ -111,11 +147,14 @@ where
     }
 
     fn file_by_name(&self, name: &str) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        self.invalidate_if_outdated();
         if self.non_files.borrow().contains(name) {
+            DIR_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
             return Err(Self::Error::not_found());
         }
 
         if let Some(node) = self.hidden_cached_files.borrow().get(name) {
+            DIR_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
             return Ok(node.clone());
         }
 
@@ -128,9 +167,11 @@ where
                 .find(|(n, _)| n == name)
                 .map(|(_, entry)| entry);
             if let Some(node) = maybe_node {
+                DIR_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
                 return Ok(node.clone());
             }
         }
+        DIR_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
 
         match self.inner.file_by_name(name) {
             Ok(node) => {
@@ -148,6 +189,30 @@ where
             }
         }
     }
+
+    fn symlink(&self, name: &str, target: &Path) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        let node = map_node(self.inner.symlink(name, target)?);
+        self.invalidate();
+        Ok(node)
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        self.inner.unlink(name)?;
+        self.invalidate();
+        Ok(())
+    }
+
+    fn mkdir(&self, name: &str) -> Result<Node<CacheRoot<N>>, Self::Error> {
+        let node = map_node(self.inner.mkdir(name)?);
+        self.invalidate();
+        Ok(node)
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Self::Error> {
+        self.inner.rmdir(name)?;
+        self.invalidate();
+        Ok(())
+    }
 }
 
 fn map_node<N>(node: Node<N>) -> Node<CacheRoot<N>>
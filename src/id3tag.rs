@@ -1,22 +1,267 @@
+use crate::chapters::Chapter;
 use crate::soundcloud;
 use chrono::Datelike;
 use id3;
+use lazy_static::lazy_static;
 use log::*;
+use regex::Regex;
 use std::io;
 
+/// The size of an ID3v1 tag.
+pub const ID3V1_LEN: usize = 128;
+
+/// The owner of the UFID and POPM frames written by us.
+const OWNER: &str = "https://soundcloud.com";
+
+/// The POPM ratings for one to five stars, as used by most players.
+const POPM_STARS: [u8; 5] = [1, 64, 128, 196, 255];
+
+/// The genres that can be expressed in an ID3v1 tag, indexed by their number.
+const ID3V1_GENRES: [&str; 80] = [
+    "Blues",
+    "Classic Rock",
+    "Country",
+    "Dance",
+    "Disco",
+    "Funk",
+    "Grunge",
+    "Hip-Hop",
+    "Jazz",
+    "Metal",
+    "New Age",
+    "Oldies",
+    "Other",
+    "Pop",
+    "R&B",
+    "Rap",
+    "Reggae",
+    "Rock",
+    "Techno",
+    "Industrial",
+    "Alternative",
+    "Ska",
+    "Death Metal",
+    "Pranks",
+    "Soundtrack",
+    "Euro-Techno",
+    "Ambient",
+    "Trip-Hop",
+    "Vocal",
+    "Jazz+Funk",
+    "Fusion",
+    "Trance",
+    "Classical",
+    "Instrumental",
+    "Acid",
+    "House",
+    "Game",
+    "Sound Clip",
+    "Gospel",
+    "Noise",
+    "AlternRock",
+    "Bass",
+    "Soul",
+    "Punk",
+    "Space",
+    "Meditative",
+    "Instrumental Pop",
+    "Instrumental Rock",
+    "Ethnic",
+    "Gothic",
+    "Darkwave",
+    "Techno-Industrial",
+    "Electronic",
+    "Pop-Folk",
+    "Eurodance",
+    "Dream",
+    "Southern Rock",
+    "Comedy",
+    "Cult",
+    "Gangsta",
+    "Top 40",
+    "Christian Rap",
+    "Pop/Funk",
+    "Jungle",
+    "Native American",
+    "Cabaret",
+    "New Wave",
+    "Psychadelic",
+    "Rave",
+    "Showtunes",
+    "Trailer",
+    "Lo-Fi",
+    "Tribal",
+    "Acid Punk",
+    "Acid Jazz",
+    "Polka",
+    "Retro",
+    "Musical",
+    "Rock & Roll",
+    "Hard Rock",
+];
+
+/// The rules that are applied to track titles by default, in order.
+const DEFAULT_TITLE_RULES: &[&str] = &[
+    // Promotional prefixes, e.g. "PREMIERE: Artist - Title".
+    r"(?i)^(?:premiere|out now|free (?:download|dl))\s*[:|]\s*(?P<title>.+)$",
+    // Promotional suffixes, e.g. "Title [FREE DOWNLOAD]".
+    r"(?i)^(?P<title>.+?)\s*[(\[](?:premiere|out now|free (?:download|dl))[)\]]$",
+    // The remixer. The remix itself stays part of the title.
+    r"(?i)[(\[](?P<remixer>[^()\[\]]+?)\s+(?:remix|rmx|bootleg|flip|edit)[)\]]",
+    // The label in brackets at the end, e.g. "Title [Ninja Tune]".
+    r"^(?P<title>.+?)\s*\[(?P<label>[^\[\]]+)\]$",
+    // "Artist - Title", where the artist may be featuring others.
+    r"(?i)^(?P<artist>.+?)(?:\s+\(?(?:feat\.?|ft\.?|featuring)\s+(?P<featuring>[^()]+?)\)?)?\s+[-–—]\s+(?P<title>.+)$",
+    // Featured artists in the title, e.g. "Title (feat. Artist) (Someone Remix)".
+    r"(?i)^(?P<title>.+?)\s*[(\[](?:feat\.?|ft\.?|featuring)\s+(?P<featuring>[^)\]]+)[)\]](?P<rest>.*)$",
+    r"(?i)^(?P<title>.+?)\s+(?:feat\.?|ft\.?|featuring)\s+(?P<featuring>[^()\[\]]+?)(?P<rest>\s*[(\[].*)?$",
+    // "(Original Mix)" does not tell anything.
+    r"(?i)^(?P<title>.+?)\s*[(\[]original mix[)\]](?P<rest>.*)$",
+];
+
+lazy_static! {
+    /// Words that describe a version of a track.
+    static ref VERSION: Regex =
+        Regex::new(r"(?i)\b(?:mix|remix|rmx|edit|bootleg|flip|vip|dub|version)\b").unwrap();
+    /// Versions that do not name anyone, e.g. "Radio" in "(Radio Edit)".
+    static ref NOT_A_REMIXER: Regex = Regex::new(
+        r"(?i)^(?:radio|extended|original|club|short|single|album|clean|dirty|vocal|instrumental)$"
+    )
+    .unwrap();
+}
+
+/// Room in a tag for artwork. The tag is padded to include the room regardless of whether the
+/// artwork is known or whether it fits, so the size of the tag can be known without downloading
+/// the artwork.
+pub struct ArtworkSlot<'a> {
+    /// The number of bytes reserved for the picture frame.
+    pub len: usize,
+    pub image: Option<&'a [u8]>,
+}
+
+impl<'a> ArtworkSlot<'a> {
+    /// Reserves enough room for a JPEG image of the specified width and height.
+    pub fn for_size(max_size: u32, image: Option<&'a [u8]>) -> Self {
+        let pixels = max_size as usize * max_size as usize;
+        ArtworkSlot {
+            // Generous for a JPEG, with some room for the frame itself.
+            len: pixels * 3 / 4 + 1024,
+            image,
+        }
+    }
+}
+
+/// The metadata that could be extracted from a track title.
+#[derive(Debug, Default, PartialEq)]
+pub struct TrackTitle {
+    pub title: String,
+    pub artist: String,
+    pub featuring: Option<String>,
+    pub remixer: Option<String>,
+    pub label: Option<String>,
+}
+
+/// TitleRules extracts metadata from track titles using a list of regular expressions.
+///
+/// The rules are tried in order. The named groups `artist`, `featuring`, `remixer` and `label` of
+/// a rule that matches are stored, unless an earlier rule already found them, in which case the
+/// rule is skipped. The `title` group, followed by the `rest` group, replaces the title that the
+/// next rules are matched against.
+///
+/// A rule is also skipped if its label names a version of the track, e.g. "[Extended Mix]", or if
+/// its remixer is just a kind of version, e.g. "(Radio Edit)".
+pub struct TitleRules {
+    rules: Vec<Regex>,
+}
+
+impl TitleRules {
+    /// Creates the default rules, preceded by the specified extra rules.
+    pub fn new<S: AsRef<str>>(extra: &[S]) -> Result<Self, regex::Error> {
+        let extra = extra.iter().map(AsRef::as_ref);
+        let rules = extra
+            .chain(DEFAULT_TITLE_RULES.iter().cloned())
+            .map(Regex::new)
+            .collect::<Result<_, _>>()?;
+        Ok(TitleRules { rules })
+    }
+
+    /// Rules that leave titles as they are.
+    pub fn none() -> Self {
+        TitleRules { rules: Vec::new() }
+    }
+
+    /// Parses a track title. The uploader is the artist if no rule finds one.
+    pub fn parse(&self, title: &str, uploader: &str) -> TrackTitle {
+        let mut title = title.trim().to_string();
+        let mut artist = None;
+        let mut featuring = None;
+        let mut remixer = None;
+        let mut label = None;
+
+        for rule in &self.rules {
+            let caps = match rule.captures(&title) {
+                Some(v) => v,
+                None => continue,
+            };
+            let group = |name| {
+                caps.name(name)
+                    .map(|m| m.as_str().trim().to_string())
+                    .filter(|s| !s.is_empty())
+            };
+            if group("label").is_some_and(|l| VERSION.is_match(&l))
+                || group("remixer").is_some_and(|r| NOT_A_REMIXER.is_match(&r))
+            {
+                continue;
+            }
+            let mut fields = [
+                (&mut artist, group("artist")),
+                (&mut featuring, group("featuring")),
+                (&mut remixer, group("remixer")),
+                (&mut label, group("label")),
+            ];
+            if fields.iter().any(|(f, v)| f.is_some() && v.is_some()) {
+                continue;
+            }
+            for (field, value) in fields.iter_mut() {
+                if value.is_some() {
+                    **field = value.take();
+                }
+            }
+            if let Some(t) = group("title") {
+                title = match group("rest") {
+                    Some(rest) => format!("{} {}", t, rest),
+                    None => t,
+                };
+            }
+        }
+
+        TrackTitle {
+            title,
+            artist: artist.unwrap_or_else(|| uploader.to_string()),
+            featuring,
+            remixer,
+            label,
+        }
+    }
+}
+
 pub fn tag_for_track(
     track: &soundcloud::Track,
-    enable_artwork: bool,
-    parse_strings: bool,
+    artwork: Option<ArtworkSlot>,
+    title_rules: &TitleRules,
+    chapters: &[Chapter],
+    comments: &[(u64, String)],
 ) -> Result<impl io::Read + io::Seek, soundcloud::Error> {
     let mut tag = id3::Tag::new();
 
-    if let Some(i) = track.title.find(" - ").filter(|_| parse_strings) {
-        tag.set_title(&track.title[..i]);
-        tag.set_artist(&track.title[i + 3..]);
-    } else {
-        tag.set_artist(track.user.username.as_str());
-        tag.set_title(track.title.as_str());
+    let parsed = title_rules.parse(&track.title, &track.user.username);
+    tag.set_title(parsed.title.as_str());
+    tag.set_artist(parsed.artist.as_str());
+    if let Some(ref featuring) = parsed.featuring {
+        tag.add_extended_text("FEATURING", featuring.as_str());
+    }
+    if let Some(ref remixer) = parsed.remixer {
+        tag.set_text("TPE4", remixer.as_str());
     }
 
     tag.set_duration(track.duration_ms as u32);
@@ -29,11 +274,7 @@ pub fn tag_for_track(
         "WOAR",
         id3::Content::Link(track.user.permalink_url.to_string()),
     ));
-    tag.set_year(
-        track
-            .release_year
-            .unwrap_or_else(|| track.created_at.date().year()),
-    );
+    tag.set_year(year(track));
     tag.set_text(
         "TDAT",
         format!(
@@ -58,28 +299,591 @@ pub fn tag_for_track(
     if let Some(bpm) = track.bpm {
         tag.set_text("TBPM", format!("{}", bpm.round()));
     }
-    if let Some(ref label) = track.label_name {
+    if let Some(label) = track.label_name.as_ref().or(parsed.label.as_ref()) {
         tag.set_text("TPUB", label.as_str());
     }
     if let Some(ref isrc) = track.isrc {
         tag.set_text("TSRC", isrc.as_str());
     }
+    if let Some(ref key) = track.key_signature {
+        tag.set_text("TKEY", key.as_str());
+    }
+    if let Some(ref url) = track.purchase_url {
+        // The purchase URL is both where the track is sold and where it can be paid for.
+        tag.add_frame(id3::Frame::with_content(
+            "WCOM",
+            id3::Content::Link(url.to_string()),
+        ));
+        tag.add_frame(id3::Frame::with_content(
+            "WPAY",
+            id3::Content::Link(url.to_string()),
+        ));
+    }
+    if let Some(ref release) = track.release {
+        tag.add_extended_text("CATALOGNUMBER", release.as_str());
+    }
+    if let Some(ref track_type) = track.track_type {
+        tag.add_extended_text("SoundCloud Track Type", track_type.as_str());
+    }
+    let tags = track.tags();
+    if !tags.is_empty() {
+        // ID3v2.4 separates multiple values with a null character.
+        tag.add_extended_text("SoundCloud Tags", tags.join("\0"));
+    }
+    tag.add_extended_text("SoundCloud Track Id", track.id.to_string());
+    tag.add_extended_text("SoundCloud Artist Id", track.user.id.to_string());
+    tag.add_frame(id3::Frame::with_content(
+        "UFID",
+        id3::Content::Unknown(ufid(track)),
+    ));
+    if track.likes_count.is_some() || track.playback_count.is_some() {
+        tag.add_frame(id3::Frame::with_content(
+            "POPM",
+            id3::Content::Unknown(popm(track)),
+        ));
+    }
 
-    if enable_artwork {
-        match track.artwork() {
-            Err(soundcloud::Error::ArtworkNotAvailable) => (),
-            Err(err) => error!("{}", err),
-            Ok((data, mime_type)) => tag.add_picture(id3::frame::Picture {
-                mime_type,
-                picture_type: id3::frame::PictureType::CoverFront,
-                description: "Artwork".to_string(),
-                data,
+    if !chapters.is_empty() {
+        add_chapters(&mut tag, chapters, title_rules);
+    }
+    if !comments.is_empty() {
+        tag.add_frame(id3::Frame::with_content(
+            "SYLT",
+            id3::Content::SynchronisedLyrics(id3::frame::SynchronisedLyrics {
+                lang: "eng".to_string(),
+                timestamp_format: id3::frame::TimestampFormat::MS,
+                content_type: id3::frame::SynchronisedLyricsType::Trivia,
+                content: comments
+                    .iter()
+                    .map(|(time, text)| (u64::min(*time, u64::from(u32::MAX)) as u32, text.clone()))
+                    .collect(),
             }),
-        }
+        ));
     }
 
     let mut id3_tag_buf = Vec::new();
     tag.write_to(&mut id3_tag_buf, id3::Version::Id3v24)
         .unwrap();
+
+    if let Some(slot) = artwork {
+        let len = id3_tag_buf.len() + slot.len;
+        if let Some(data) = slot.image {
+            tag.add_picture(id3::frame::Picture {
+                mime_type: image_mime_type(data).to_string(),
+                picture_type: id3::frame::PictureType::CoverFront,
+                description: "Artwork".to_string(),
+                data: data.to_vec(),
+            });
+            let mut buf = Vec::new();
+            tag.write_to(&mut buf, id3::Version::Id3v24).unwrap();
+            if buf.len() <= len {
+                id3_tag_buf = buf;
+            } else {
+                warn!(
+                    "artwork of track {} does not fit in {} bytes",
+                    track.id, slot.len
+                );
+            }
+        }
+        pad_tag(&mut id3_tag_buf, len);
+    }
     Ok(io::Cursor::new(id3_tag_buf))
 }
+
+/// Builds an ID3v1.1 tag from the same data as the ID3v2 tag. Text that does not fit is
+/// truncated and characters outside of Latin-1 are replaced.
+pub fn id3v1_for_track(track: &soundcloud::Track, title_rules: &TitleRules) -> [u8; ID3V1_LEN] {
+    let mut tag = [0; ID3V1_LEN];
+    let parsed = title_rules.parse(&track.title, &track.user.username);
+    tag[0..3].copy_from_slice(b"TAG");
+    copy_latin1(&mut tag[3..33], &parsed.title);
+    copy_latin1(&mut tag[33..63], &parsed.artist);
+    // 63..93: The album, which tracks do not have.
+    copy_latin1(&mut tag[93..97], &format!("{:04}", year(track)));
+    // 97..125: The comment. 125 is 0 to mark this as ID3v1.1, 126 is the track number, which is
+    // unknown.
+    tag[127] = track
+        .genre
+        .as_ref()
+        .and_then(|genre| {
+            ID3V1_GENRES
+                .iter()
+                .position(|g| g.eq_ignore_ascii_case(genre))
+        })
+        .map(|i| i as u8)
+        .unwrap_or(0xff);
+    tag
+}
+
+/// Adds a CHAP frame for every chapter and a CTOC frame that lists them in order. The id3 crate
+/// does not know these frames, so they are encoded as described by the ID3v2 Chapter Frame
+/// Addendum.
+fn add_chapters(tag: &mut id3::Tag, chapters: &[Chapter], title_rules: &TitleRules) {
+    // The table of contents can only refer to this many chapters.
+    let chapters = &chapters[..chapters.len().min(u8::MAX as usize)];
+    let element_id = |i: usize| format!("chp{}", i);
+
+    for (i, chapter) in chapters.iter().enumerate() {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(element_id(i).as_bytes());
+        buf.push(0);
+        buf.extend_from_slice(&(chapter.start_ms.min(u64::from(u32::MAX)) as u32).to_be_bytes());
+        buf.extend_from_slice(&(chapter.end_ms.min(u64::from(u32::MAX)) as u32).to_be_bytes());
+        // The byte offsets are unknown, which is expressed by setting all bits.
+        buf.extend_from_slice(&[0xff; 8]);
+        let parsed = title_rules.parse(&chapter.title, "");
+        buf.extend(embedded_text_frame("TIT2", &parsed.title));
+        if !parsed.artist.is_empty() {
+            buf.extend(embedded_text_frame("TPE1", &parsed.artist));
+        }
+        tag.add_frame(id3::Frame::with_content("CHAP", id3::Content::Unknown(buf)));
+    }
+
+    let mut buf = Vec::new();
+    buf.extend_from_slice(b"toc\0");
+    // Top-level and ordered.
+    buf.push(0x03);
+    buf.push(chapters.len() as u8);
+    for i in 0..chapters.len() {
+        buf.extend_from_slice(element_id(i).as_bytes());
+        buf.push(0);
+    }
+    tag.add_frame(id3::Frame::with_content("CTOC", id3::Content::Unknown(buf)));
+}
+
+/// Encodes an ID3v2.4 text frame to be embedded in another frame.
+fn embedded_text_frame(id: &str, text: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(id.as_bytes());
+    buf.extend_from_slice(&syncsafe(1 + text.len() as u32).to_be_bytes());
+    // No flags.
+    buf.extend_from_slice(&[0, 0]);
+    // UTF-8.
+    buf.push(3);
+    buf.extend_from_slice(text.as_bytes());
+    buf
+}
+
+/// Grows an encoded tag to the specified length by adding padding.
+fn pad_tag(buf: &mut Vec<u8>, len: usize) {
+    buf.resize(len, 0);
+    // The size in the header excludes the header itself.
+    let size = syncsafe((len - 10) as u32);
+    buf[6..10].copy_from_slice(&size.to_be_bytes());
+}
+
+fn syncsafe(n: u32) -> u32 {
+    (n & 0x7f) | (n & 0x3f80) << 1 | (n & 0x001f_c000) << 2 | (n & 0x0fe0_0000) << 3
+}
+
+/// Artwork is usually JPEG, but may also be PNG.
+fn image_mime_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG") {
+        "image/png"
+    } else {
+        "image/jpeg"
+    }
+}
+
+/// Builds the body of a UFID frame identifying the track by its SoundCloud id.
+fn ufid(track: &soundcloud::Track) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend_from_slice(OWNER.as_bytes());
+    buf.push(0);
+    buf.extend_from_slice(track.id.to_string().as_bytes());
+    buf
+}
+
+/// Builds the body of a POPM frame. The rating is one star for every order of magnitude of likes,
+/// the counter is the play count.
+fn popm(track: &soundcloud::Track) -> Vec<u8> {
+    let rating = match track.likes_count {
+        Some(likes) if likes > 0 => {
+            let stars = (likes as f64).log10().floor() as usize;
+            POPM_STARS[stars.min(POPM_STARS.len() - 1)]
+        }
+        _ => 0,
+    };
+    let mut buf = Vec::new();
+    buf.extend_from_slice(OWNER.as_bytes());
+    buf.push(0);
+    buf.push(rating);
+    // The counter is at least 4 bytes and grows when needed.
+    let counter = track.playback_count.unwrap_or(0).max(0) as u64;
+    let counter = counter.to_be_bytes();
+    let skip = counter.iter().take(4).take_while(|&&b| b == 0).count();
+    buf.extend_from_slice(&counter[skip..]);
+    buf
+}
+
+fn year(track: &soundcloud::Track) -> i32 {
+    track
+        .release_year
+        .unwrap_or_else(|| track.created_at.date().year())
+}
+
+fn copy_latin1(buf: &mut [u8], s: &str) {
+    let chars = s
+        .chars()
+        .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' });
+    for (b, c) in buf.iter_mut().zip(chars) {
+        *b = c;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Seek;
+
+    fn read_tag(track: &soundcloud::Track) -> id3::Tag {
+        id3::Tag::read_from(
+            tag_for_track(
+                track,
+                None,
+                &TitleRules::new::<&str>(&[]).unwrap(),
+                &[],
+                &[],
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn extended_text<'a>(tag: &'a id3::Tag, description: &str) -> Option<&'a str> {
+        tag.extended_texts()
+            .find(|t| t.description == description)
+            .map(|t| t.value.as_str())
+    }
+
+    fn unknown<'a>(tag: &'a id3::Tag, id: &str) -> Option<&'a [u8]> {
+        tag.get(id).and_then(|f| match f.content() {
+            id3::Content::Unknown(data) => Some(data.as_slice()),
+            _ => None,
+        })
+    }
+
+    fn title(title: &str, artist: &str) -> TrackTitle {
+        TrackTitle {
+            title: title.to_string(),
+            artist: artist.to_string(),
+            ..TrackTitle::default()
+        }
+    }
+
+    #[test]
+    fn title_rules() {
+        let rules = TitleRules::new::<&str>(&[]).unwrap();
+        let parse = |t| rules.parse(t, "Uploader");
+
+        assert_eq!(
+            title("The Fat Dandy Butterfly Slims", "Uploader"),
+            parse("The Fat Dandy Butterfly Slims"),
+        );
+        assert_eq!(
+            TrackTitle {
+                label: Some("Steel City Dance Discs".to_string()),
+                ..title("Can't", "Mall Grab")
+            },
+            parse("Mall Grab - Can't (Original Mix) [Steel City Dance Discs]"),
+        );
+        assert_eq!(
+            TrackTitle {
+                featuring: Some("Sam Smith".to_string()),
+                ..title("Latch", "Disclosure")
+            },
+            parse("Disclosure - Latch (feat. Sam Smith)"),
+        );
+        assert_eq!(
+            TrackTitle {
+                featuring: Some("Kai".to_string()),
+                ..title("Never Be Like You", "Flume")
+            },
+            parse("Flume feat. Kai - Never Be Like You"),
+        );
+        assert_eq!(
+            TrackTitle {
+                featuring: Some("Zyra".to_string()),
+                remixer: Some("RAC".to_string()),
+                ..title("Say My Name (RAC Remix)", "ODESZA")
+            },
+            parse("ODESZA - Say My Name (feat. Zyra) (RAC Remix)"),
+        );
+        assert_eq!(
+            TrackTitle {
+                featuring: Some("Jamie xx".to_string()),
+                ..title("Take It Easy (Extended Mix)", "Romy")
+            },
+            parse("Romy - Take It Easy ft. Jamie xx (Extended Mix)"),
+        );
+        assert_eq!(
+            TrackTitle {
+                label: Some("Ninja Tune".to_string()),
+                ..title("Glue", "Bicep")
+            },
+            parse("PREMIERE: Bicep - Glue [Ninja Tune]"),
+        );
+        assert_eq!(
+            title("Elixir", "Tourist"),
+            parse("Tourist - Elixir [FREE DOWNLOAD]"),
+        );
+    }
+
+    #[test]
+    fn title_rules_versions() {
+        let rules = TitleRules::new::<&str>(&[]).unwrap();
+        let cases = [
+            (
+                "Artist - Song [RAC Remix]",
+                TrackTitle {
+                    remixer: Some("RAC".to_string()),
+                    ..title("Song [RAC Remix]", "Artist")
+                },
+            ),
+            (
+                "Artist - Song [Extended Mix]",
+                title("Song [Extended Mix]", "Artist"),
+            ),
+            (
+                "Artist - Song (Radio Edit)",
+                title("Song (Radio Edit)", "Artist"),
+            ),
+            (
+                "Artist - Song [Extended Edit]",
+                title("Song [Extended Edit]", "Artist"),
+            ),
+            ("Artist - Song [VIP]", title("Song [VIP]", "Artist")),
+            (
+                "Artist - Song (Kaytranada Edit) [Ninja Tune]",
+                TrackTitle {
+                    remixer: Some("Kaytranada".to_string()),
+                    label: Some("Ninja Tune".to_string()),
+                    ..title("Song (Kaytranada Edit)", "Artist")
+                },
+            ),
+            (
+                "Artist - Song (Original Mix) [Mixmash Records]",
+                TrackTitle {
+                    label: Some("Mixmash Records".to_string()),
+                    ..title("Song", "Artist")
+                },
+            ),
+        ];
+        for (input, expected) in cases.iter() {
+            assert_eq!(expected, &rules.parse(input, "Uploader"), "{}", input);
+        }
+    }
+
+    #[test]
+    fn title_rules_extra() {
+        let rules = TitleRules::new(&[r"^(?P<title>.+) // (?P<artist>.+)$"]).unwrap();
+        assert_eq!(
+            TrackTitle {
+                remixer: Some("Someone".to_string()),
+                ..title("Title - Part 2 (Someone Remix)", "Artist")
+            },
+            rules.parse("Title - Part 2 (Someone Remix) // Artist", "Uploader"),
+        );
+
+        assert!(TitleRules::new(&["(unclosed"]).is_err());
+    }
+
+    #[test]
+    fn track_frames() {
+        let tag = read_tag(&soundcloud::Track::example());
+        assert_eq!(Some("Am"), tag.get("TKEY").and_then(|f| f.content().text()));
+        for id in &["WCOM", "WPAY"] {
+            assert_eq!(
+                Some("https://wrightandbastard.bandcamp.com/"),
+                tag.get(id).and_then(|f| f.content().link()),
+            );
+        }
+        assert_eq!(Some("WAB-004"), extended_text(&tag, "CATALOGNUMBER"));
+        assert_eq!(
+            Some("original"),
+            extended_text(&tag, "SoundCloud Track Type"),
+        );
+        assert_eq!(
+            Some("electronic\0Wright and Bastard\0dandy"),
+            extended_text(&tag, "SoundCloud Tags"),
+        );
+        assert_eq!(
+            Some("609233313"),
+            extended_text(&tag, "SoundCloud Track Id"),
+        );
+        assert_eq!(
+            Some("14559536"),
+            extended_text(&tag, "SoundCloud Artist Id"),
+        );
+        assert_eq!(
+            Some(&b"https://soundcloud.com\x00609233313"[..]),
+            unknown(&tag, "UFID"),
+        );
+        assert_eq!(
+            Some(&b"https://soundcloud.com\0\x40\0\0\x04\xd2"[..]),
+            unknown(&tag, "POPM"),
+        );
+    }
+
+    #[test]
+    fn chapter_frames() {
+        let chapters = [
+            Chapter {
+                start_ms: 0,
+                end_ms: 252_000,
+                title: "Bicep - Glue".to_string(),
+            },
+            Chapter {
+                start_ms: 252_000,
+                end_ms: 300_000,
+                title: "Untitled".to_string(),
+            },
+        ];
+        let rules = TitleRules::new::<&str>(&[]).unwrap();
+        let track = soundcloud::Track::example();
+        let tag = tag_for_track(&track, None, &rules, &chapters, &[]).unwrap();
+        let tag = id3::Tag::read_from(tag).unwrap();
+
+        let chap: Vec<_> = tag
+            .frames()
+            .filter(|f| f.id() == "CHAP")
+            .map(|f| match f.content() {
+                id3::Content::Unknown(data) => data.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                [
+                    &b"chp0\0\0\0\0\0\0\x03\xd8\x60"[..],
+                    &[0xff; 8],
+                    b"TIT2\0\0\0\x05\0\0\x03Glue",
+                    b"TPE1\0\0\0\x06\0\0\x03Bicep",
+                ]
+                .concat(),
+                [
+                    &b"chp1\0\0\x03\xd8\x60\0\x04\x93\xe0"[..],
+                    &[0xff; 8],
+                    b"TIT2\0\0\0\x09\0\0\x03Untitled",
+                ]
+                .concat(),
+            ],
+            chap,
+        );
+        assert_eq!(
+            Some(&b"toc\0\x03\x02chp0\0chp1\0"[..]),
+            unknown(&tag, "CTOC"),
+        );
+    }
+
+    #[test]
+    fn comment_frame() {
+        let comments = [
+            (1_500, "A: first".to_string()),
+            (63_345, "B: second".to_string()),
+        ];
+        let rules = TitleRules::none();
+        let track = soundcloud::Track::example();
+        let tag = tag_for_track(&track, None, &rules, &[], &comments).unwrap();
+        let tag = id3::Tag::read_from(tag).unwrap();
+
+        let sylt = tag.synchronised_lyrics().next().unwrap();
+        assert_eq!(id3::frame::TimestampFormat::MS, sylt.timestamp_format);
+        assert_eq!(
+            vec![
+                (1_500, "A: first".to_string()),
+                (63_345, "B: second".to_string())
+            ],
+            sylt.content
+        );
+    }
+
+    #[test]
+    fn embedded_text_frame_size() {
+        let frame = embedded_text_frame("TIT2", &"x".repeat(200));
+        assert_eq!(b"TIT2\0\0\x01\x49", &frame[..8]);
+    }
+
+    #[test]
+    fn artwork_slot() {
+        let track = soundcloud::Track::example();
+        let rules = TitleRules::none();
+        let tag_len = |image| {
+            let slot = ArtworkSlot { len: 100, image };
+            let mut tag = tag_for_track(&track, Some(slot), &rules, &[], &[]).unwrap();
+            tag.seek(io::SeekFrom::End(0)).unwrap()
+        };
+        let bare_len = {
+            let mut tag = tag_for_track(&track, None, &rules, &[], &[]).unwrap();
+            tag.seek(io::SeekFrom::End(0)).unwrap()
+        };
+
+        let png = b"\x89PNG\r\n\x1a\n";
+        assert_eq!(bare_len + 100, tag_len(None));
+        assert_eq!(bare_len + 100, tag_len(Some(png)));
+        assert_eq!(bare_len + 100, tag_len(Some(&[0; 1000])));
+
+        let slot = ArtworkSlot {
+            len: 100,
+            image: Some(png),
+        };
+        let tag = tag_for_track(&track, Some(slot), &rules, &[], &[]).unwrap();
+        let tag = id3::Tag::read_from(tag).unwrap();
+        let picture = tag.pictures().next().unwrap();
+        assert_eq!("image/png", picture.mime_type);
+        assert_eq!(&png[..], &picture.data[..]);
+
+        let slot = ArtworkSlot {
+            len: 100,
+            image: Some(&[0; 1000]),
+        };
+        let tag = tag_for_track(&track, Some(slot), &rules, &[], &[]).unwrap();
+        let tag = id3::Tag::read_from(tag).unwrap();
+        assert_eq!(0, tag.pictures().count());
+    }
+
+    #[test]
+    fn popm_rating() {
+        let mut track = soundcloud::Track::example();
+        let rating = |track: &soundcloud::Track| popm(track)[OWNER.len() + 1];
+        for &(likes, expected) in &[
+            (0, 0),
+            (1, 1),
+            (9, 1),
+            (10, 64),
+            (999, 128),
+            (10_000_000, 255),
+        ] {
+            track.likes_count = Some(likes);
+            assert_eq!(expected, rating(&track), "likes: {}", likes);
+        }
+
+        track.playback_count = Some(0x1_2345_6789);
+        assert_eq!(
+            &[1, 0x23, 0x45, 0x67, 0x89],
+            &popm(&track)[OWNER.len() + 2..]
+        );
+    }
+
+    #[test]
+    fn id3v1() {
+        let mut track = soundcloud::Track::example();
+        track.title = "Pädde - A title that does not fit in the tag ✓".to_string();
+
+        let rules = TitleRules::new::<&str>(&[]).unwrap();
+        let tag = id3v1_for_track(&track, &rules);
+        assert_eq!(b"TAG", &tag[0..3]);
+        assert_eq!(b"A title that does not fit in t", &tag[3..33]);
+        assert_eq!(b"P\xe4dde\0", &tag[33..39]);
+        assert_eq!([0; 30], tag[63..93]);
+        assert_eq!(b"2019", &tag[93..97]);
+        assert_eq!(52, tag[127]);
+
+        let tag = id3v1_for_track(&track, &TitleRules::none());
+        assert_eq!(b"P\xe4dde - A title that does not ", &tag[3..33]);
+        assert_eq!(b"Wright and Bastard\0", &tag[33..52]);
+
+        track.genre = Some("Drum & Bass".to_string());
+        assert_eq!(0xff, id3v1_for_track(&track, &rules)[127]);
+    }
+}
@@ -0,0 +1,147 @@
+use super::Pattern;
+use std::io;
+
+/// Fit presents a stream as having an exact size. If the inner stream is shorter, the remainder is
+/// filled by repeating a pattern. If it is longer, it is truncated.
+///
+/// The size of the inner stream is only queried once reading hits its end.
+pub struct Fit<T, P>
+where
+    T: io::Read + io::Seek,
+    P: AsRef<[u8]>,
+{
+    inner: T,
+    size: u64,
+    pad: Pattern<P>,
+    offset: u64,
+    inner_end: Option<u64>,
+}
+
+impl<T, P> Fit<T, P>
+where
+    T: io::Read + io::Seek,
+    P: AsRef<[u8]>,
+{
+    pub fn new(inner: T, size: u64, pad: P) -> Self {
+        Fit {
+            inner,
+            size,
+            pad: Pattern::new(pad, u64::MAX),
+            offset: 0,
+            inner_end: None,
+        }
+    }
+}
+
+impl<T, P> io::Read for Fit<T, P>
+where
+    T: io::Read + io::Seek,
+    P: AsRef<[u8]>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.size.saturating_sub(self.offset);
+        let buf_len = remaining.min(buf.len() as u64) as usize;
+        let buf = &mut buf[..buf_len];
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let inner_end = match self.inner_end {
+            Some(end) if self.offset >= end => end,
+            _ => {
+                let nread = self.inner.read(buf)?;
+                if nread > 0 {
+                    self.offset += nread as u64;
+                    return Ok(nread);
+                }
+                // The reader may have been seeked beyond the end, so the end is asked for rather
+                // than assumed to be here. The pattern must line up with the actual end.
+                let end = self.inner.seek(io::SeekFrom::End(0))?.min(self.offset);
+                self.inner_end = Some(end);
+                end
+            }
+        };
+
+        io::Seek::seek(&mut self.pad, io::SeekFrom::Start(self.offset - inner_end))?;
+        let nread = io::Read::read(&mut self.pad, buf)?;
+        self.offset += nread as u64;
+        Ok(nread)
+    }
+}
+
+impl<T, P> io::Seek for Fit<T, P>
+where
+    T: io::Read + io::Seek,
+    P: AsRef<[u8]>,
+{
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let new_offset = match pos {
+            io::SeekFrom::Start(offset) => offset as i64,
+            io::SeekFrom::Current(offset) => self.offset as i64 + offset,
+            io::SeekFrom::End(offset) => self.size as i64 + offset,
+        };
+        if new_offset < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "ioutil::Fit: seek position {:?} resolves to {}",
+                    pos, new_offset
+                ),
+            ));
+        }
+        self.offset = new_offset as u64;
+        if self.inner_end.map(|end| self.offset < end).unwrap_or(true) {
+            self.inner.seek(io::SeekFrom::Start(self.offset))?;
+        }
+        Ok(self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Seek};
+
+    fn read_all(r: &mut impl Read) -> Vec<u8> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn exact() {
+        let mut fit = Fit::new(io::Cursor::new(vec![1, 2, 3, 4]), 4, [0]);
+        assert_eq!(vec![1, 2, 3, 4], read_all(&mut fit));
+    }
+
+    #[test]
+    fn pad() {
+        let mut fit = Fit::new(io::Cursor::new(vec![1, 2, 3]), 8, [7, 8]);
+        assert_eq!(vec![1, 2, 3, 7, 8, 7, 8, 7], read_all(&mut fit));
+
+        // The pattern is aligned to the end of the inner stream after seeking.
+        fit.seek(io::SeekFrom::Start(4)).unwrap();
+        assert_eq!(vec![8, 7, 8, 7], read_all(&mut fit));
+        fit.seek(io::SeekFrom::Start(1)).unwrap();
+        assert_eq!(vec![2, 3, 7, 8, 7, 8, 7], read_all(&mut fit));
+    }
+
+    #[test]
+    fn pad_after_seek_past_end() {
+        let mut fit = Fit::new(io::Cursor::new(vec![1, 2, 3]), 8, [7, 8]);
+        fit.seek(io::SeekFrom::Start(4)).unwrap();
+        assert_eq!(vec![8, 7, 8, 7], read_all(&mut fit));
+
+        fit.seek(io::SeekFrom::Start(0)).unwrap();
+        assert_eq!(vec![1, 2, 3, 7, 8, 7, 8, 7], read_all(&mut fit));
+    }
+
+    #[test]
+    fn truncate() {
+        let mut fit = Fit::new(io::Cursor::new(vec![1, 2, 3, 4, 5, 6]), 4, [0]);
+        assert_eq!(vec![1, 2, 3, 4], read_all(&mut fit));
+        assert_eq!(4, fit.seek(io::SeekFrom::End(0)).unwrap());
+        assert_eq!(2, fit.seek(io::SeekFrom::End(-2)).unwrap());
+        assert_eq!(vec![3, 4], read_all(&mut fit));
+    }
+}
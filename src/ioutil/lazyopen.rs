@@ -92,12 +92,14 @@ where
     T: io::Read + io::Seek,
 {
     fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
-        if let Some(s) = self.size_hint {
+        // The hint is only used to avoid opening the file. Once it is open, the file knows best.
+        if let (Some(s), State::Unopened(_)) = (self.size_hint, &self.state) {
             if pos == io::SeekFrom::End(0) {
                 self.size_hint_seek_dirty = Some(pos);
                 return Ok(s);
             }
         }
+        self.size_hint_seek_dirty = None;
         let file = self.file_mut()?;
         file.seek(pos)
     }
@@ -132,4 +134,20 @@ mod tests {
         assert_eq!(nread, 2);
         assert_eq!(buf, vec![3, 4]);
     }
+
+    #[test]
+    fn size_hint() {
+        let data = vec![1, 2, 3, 4];
+        let mut file = LazyOpen::with_size_hint(8, || Ok(io::Cursor::new(data)));
+        assert_eq!(8, file.seek(io::SeekFrom::End(0)).unwrap());
+
+        // Seeking elsewhere afterwards is not undone by the deferred seek to the end.
+        assert_eq!(1, file.seek(io::SeekFrom::Start(1)).unwrap());
+        let mut buf = vec![0; 1];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(vec![2], buf);
+
+        // The actual size is reported once the file is open.
+        assert_eq!(4, file.seek(io::SeekFrom::End(0)).unwrap());
+    }
 }
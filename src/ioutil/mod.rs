@@ -1,4 +1,5 @@
 mod concat;
+mod fit;
 mod lazyopen;
 mod pattern;
 mod readseek;
@@ -8,6 +9,7 @@ mod skip;
 mod oprecorder;
 
 pub use self::concat::*;
+pub use self::fit::*;
 pub use self::lazyopen::*;
 pub use self::pattern::*;
 pub use self::readseek::*;
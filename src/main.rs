@@ -3,18 +3,25 @@
 #[macro_use]
 extern crate serde_derive;
 
+mod cache;
+mod chapters;
+mod comments;
 mod filesystem;
 mod id3tag;
 mod ioutil;
 mod mapping;
 mod mp3;
 mod soundcloud;
+mod state;
 
 use self::filesystem::*;
 use self::mapping::*;
 use log::*;
+use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::path::PathBuf;
 use std::process;
+use std::sync::Mutex;
 
 fn main() {
     env_logger::init();
@@ -35,9 +42,9 @@ fn main() {
                 .short("u")
                 .long("user")
                 .takes_value(true)
-                .required(true)
+                .required_unless("state-file")
                 .multiple(true)
-                .help("Sets the user to create directory and file entries for"),
+                .help("Sets the user to create directory and file entries for. More users can be added while mounted with mkdir in the mount point or by writing their names to .control/add-user, and removed with rmdir"),
         ).arg(
             clap::Arg::with_name("login")
                 .long("login")
@@ -46,7 +53,7 @@ fn main() {
                 .validator(|s| match s.splitn(2, ':').count() {
                     2 => Ok(()),
                     c => Err(format!("bad credential format, split on : yields {} strings", c)),
-                }).help("Logs in using a username and password instead of accessing the API anonymously"),
+                }).help("Logs in using a username and password instead of accessing the API anonymously. The account is shown as \"me\", where symlinking audio files into favorites likes them, symlinking user directories into following follows them and removing the links undoes that. Sets are created with mkdir in playlists, filled by symlinking audio files into them and deleted with rmdir once their tracks have been unlinked; the cover.jpg and sidecar files of a set do not need to be removed"),
         ).arg(
             clap::Arg::with_name("mpeg-padding")
                 .long("mpeg-padding")
@@ -54,7 +61,7 @@ fn main() {
                 .takes_value(true)
                 .default_value("1")
                 .possible_values(&["0", "1"])
-                .help("Enables rewriting parts of the MPEG stream to speed up indexing of media libraries"),
+                .help("Enables rewriting parts of the MPEG stream to speed up indexing of media libraries. This inserts silence before and after the audio, use 0 for gapless playback"),
         ).arg(
             clap::Arg::with_name("id3-images")
                 .long("id3-images")
@@ -62,7 +69,55 @@ fn main() {
                 .takes_value(true)
                 .default_value("0")
                 .possible_values(&["0", "1"])
-                .help("Enables image metadata in ID3 tags. This will incur an additional HTTP request everytime a file is opened for reading"),
+                .help("Enables image metadata in ID3 tags. The artwork is downloaded when a file is first opened for reading and then cached"),
+        ).arg(
+            clap::Arg::with_name("artwork-size")
+                .long("artwork-size")
+                .value_name("pixels")
+                .takes_value(true)
+                .default_value("500")
+                .validator(|s| s.parse::<u32>().map(|_| ()).map_err(|err| err.to_string()))
+                .help("Sets the maximum width and height of artwork. The largest size offered by SoundCloud that fits is used, which is at most 500"),
+        ).arg(
+            clap::Arg::with_name("artwork-files")
+                .long("artwork-files")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Exposes the artwork of tracks as .jpg files next to the audio files"),
+        ).arg(
+            clap::Arg::with_name("json-files")
+                .long("json-files")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Exposes the metadata of tracks as returned by SoundCloud as .json files next to the audio files"),
+        ).arg(
+            clap::Arg::with_name("waveform-files")
+                .long("waveform-files")
+                .value_name("format")
+                .takes_value(true)
+                .default_value("none")
+                .possible_values(&["none", "json", "png"])
+                .help("Exposes the waveforms of tracks as .waveform.json files with normalized samples or as .waveform.png images next to the audio files"),
+        ).arg(
+            clap::Arg::with_name("comment-files")
+                .long("comment-files")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Exposes the timed comments of tracks as .comments.txt files next to the audio files"),
+        ).arg(
+            clap::Arg::with_name("id3-comments")
+                .long("id3-comments")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Writes the timed comments of tracks to the ID3 tag so players can show them during playback. The comments are fetched once per mount when a file is first opened, so its size may change then"),
         ).arg(
             clap::Arg::with_name("id3-parse-strings")
                 .long("id3-parse-strings")
@@ -71,8 +126,74 @@ fn main() {
                 .default_value("1")
                 .possible_values(&["0", "1"])
                 .help("Looks into common patterns in track metadata to attempt to determine more accurate ID3 metadata"),
+        ).arg(
+            clap::Arg::with_name("id3-title-rule")
+                .long("id3-title-rule")
+                .value_name("regex")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|s| regex::Regex::new(&s).map(|_| ()).map_err(|err| err.to_string()))
+                .help("Adds a pattern for extracting metadata from track titles, tried before the built-in ones. The named groups artist, title, featuring, remixer and label are extracted; rest is appended to the title"),
+        ).arg(
+            clap::Arg::with_name("chapters")
+                .long("chapters")
+                .value_name("mode")
+                .takes_value(true)
+                .default_value("id3")
+                .possible_values(&["none", "id3", "cue"])
+                .help("Makes the tracklist in the description of a track available as chapters, either in the ID3 tag or as a .cue file next to the audio"),
+        ).arg(
+            clap::Arg::with_name("id3v1")
+                .long("id3v1")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Appends an ID3v1 tag to audio files for players that do not read ID3v2"),
+        ).arg(
+            clap::Arg::with_name("exact-size")
+                .long("exact-size")
+                .value_name("enable")
+                .takes_value(true)
+                .default_value("0")
+                .possible_values(&["0", "1"])
+                .help("Reports the actual size of audio files that have been opened before instead of an estimate. Use with --cache-dir to remember sizes across mounts"),
+        ).arg(
+            clap::Arg::with_name("cache-dir")
+                .long("cache-dir")
+                .value_name("dir")
+                .takes_value(true)
+                .help("Stores data that is expensive to obtain, such as the size of audio files, in the directory"),
+        ).arg(
+            clap::Arg::with_name("state-file")
+                .long("state-file")
+                .value_name("file")
+                .takes_value(true)
+                .help("Remembers the users that are added or removed while mounted in the file, so they are shown again on the next mount along with those set by --user"),
+        ).arg(
+            clap::Arg::with_name("record-http")
+                .long("record-http")
+                .value_name("dir")
+                .takes_value(true)
+                .conflicts_with("replay-http")
+                .help("Writes every HTTP request and response to the directory so it can be replayed later. Note that the recording may contain credentials when used with --login"),
+        ).arg(
+            clap::Arg::with_name("replay-http")
+                .long("replay-http")
+                .value_name("dir")
+                .takes_value(true)
+                .help("Serves HTTP responses from a directory written by --record-http instead of accessing the network"),
         ).get_matches();
 
+    if let Some(dir) = cli.value_of("record-http") {
+        info!("recording HTTP exchanges to {}", dir);
+        soundcloud::set_fixtures(Some(soundcloud::Fixtures::Record(dir.into())));
+    } else if let Some(dir) = cli.value_of("replay-http") {
+        info!("replaying HTTP exchanges from {}", dir);
+        soundcloud::set_fixtures(Some(soundcloud::Fixtures::Replay(dir.into())));
+    }
+
     let login = cli.value_of("login").and_then(|s| {
         let mut i = s.splitn(2, ':');
         let u = i.next().unwrap();
@@ -97,12 +218,78 @@ fn main() {
         }
     };
 
+    // Only the logged in account can be changed through the filesystem.
+    let me = match login {
+        None => None,
+        Some(_) => match sc_client.me() {
+            Ok(v) => Some(v),
+            Err(err) => {
+                error!("could not get the logged in account: {}", err);
+                process::exit(1);
+            }
+        },
+    };
+
+    let state_file = cli.value_of("state-file").map(PathBuf::from);
+    let mut show = match &state_file {
+        None => Vec::new(),
+        Some(path) => match state::load_users(path) {
+            Ok(v) => v,
+            Err(err) => {
+                error!("could not read state from {}: {}", path.display(), err);
+                process::exit(1);
+            }
+        },
+    };
+    for name in cli.values_of("user").into_iter().flatten() {
+        if !show.iter().any(|n| n == name) {
+            show.push(name.to_string());
+        }
+    }
+
+    let title_rules = if cli.value_of("id3-parse-strings") == Some("1") {
+        let extra: Vec<_> = cli
+            .values_of("id3-title-rule")
+            .into_iter()
+            .flatten()
+            .collect();
+        id3tag::TitleRules::new(&extra).unwrap()
+    } else {
+        id3tag::TitleRules::none()
+    };
+
     let root = RootState {
         sc_client,
-        show: cli.values_of("user").unwrap().map(str::to_string).collect(),
+        me,
+        show: Mutex::new(show),
+        state_file,
         mpeg_padding: cli.value_of("mpeg-padding") == Some("1"),
         id3_download_images: cli.value_of("id3-images") == Some("1"),
-        id3_parse_strings: cli.value_of("id3-parse-strings") == Some("1"),
+        artwork_size: cli
+            .value_of("artwork-size")
+            .unwrap()
+            .parse::<u32>()
+            .unwrap()
+            .min(soundcloud::MAX_ARTWORK_SIZE),
+        artwork_files: cli.value_of("artwork-files") == Some("1"),
+        json_files: cli.value_of("json-files") == Some("1"),
+        waveform_files: match cli.value_of("waveform-files") {
+            Some("json") => WaveformFiles::Json,
+            Some("png") => WaveformFiles::Png,
+            _ => WaveformFiles::None,
+        },
+        title_rules,
+        chapters: match cli.value_of("chapters") {
+            Some("id3") => chapters::ChapterMode::ID3,
+            Some("cue") => chapters::ChapterMode::Cue,
+            _ => chapters::ChapterMode::None,
+        },
+        id3v1: cli.value_of("id3v1") == Some("1"),
+        comments: Mutex::new(HashMap::new()),
+        comment_files: cli.value_of("comment-files") == Some("1"),
+        id3_comments: cli.value_of("id3-comments") == Some("1"),
+        exact_size: cli.value_of("exact-size") == Some("1"),
+        cache: cache::Cache::new(cli.value_of("cache-dir").map(PathBuf::from)),
     };
 
     let uid = nix::unistd::Uid::current().as_raw() as u32;
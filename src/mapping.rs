@@ -1,23 +1,40 @@
+use crate::cache::Cache;
+use crate::chapters::{self, ChapterMode};
+use crate::comments;
 use crate::filesystem;
-use crate::id3tag::tag_for_track;
-use crate::ioutil::{Concat, LazyOpen, ReadSeek, Skip};
+use crate::id3tag::{id3v1_for_track, tag_for_track, ArtworkSlot, TitleRules, ID3V1_LEN};
+use crate::ioutil::{Concat, Fit, LazyOpen, ReadSeek, Skip};
 use crate::mp3;
 use crate::soundcloud;
+use crate::state;
 use chrono::Utc;
 use id3;
+use log::*;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
 use std::io::{self, Seek};
-use std::path::PathBuf;
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 const PADDING_START: u64 = 500;
 const PADDING_END: u64 = 20;
 
+const XATTR_PREFIX: &str = "user.soundcloud.";
+
 #[derive(Debug)]
 pub enum Error {
     ChildNotFound,
+    /// The directory can not be changed, e.g. because it does not belong to the logged in account.
+    ReadOnly,
+    /// A symlink target that does not point to anything that can be linked.
+    BadLinkTarget(PathBuf),
+    /// A name that can not be given to the object that is created.
+    BadName(String),
+    NotEmpty,
 
-    SoundCloudError(soundcloud::Error),
+    SoundCloudError(Box<soundcloud::Error>),
     IOError(io::Error),
     ID3Error(id3::Error),
 }
@@ -27,9 +44,17 @@ impl filesystem::Error for Error {
         Error::ChildNotFound
     }
 
+    fn read_only() -> Self {
+        Error::ReadOnly
+    }
+
     fn errno(&self) -> i32 {
         match self {
             Error::ChildNotFound => libc::ENOENT,
+            Error::ReadOnly => libc::EROFS,
+            Error::BadLinkTarget(_) => libc::EINVAL,
+            Error::BadName(_) => libc::EINVAL,
+            Error::NotEmpty => libc::ENOTEMPTY,
             Error::SoundCloudError(_) => libc::EIO,
             Error::IOError(err) => err.raw_os_error().unwrap_or(libc::EIO),
             Error::ID3Error(_) => libc::EIO,
@@ -39,7 +64,18 @@ impl filesystem::Error for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::ChildNotFound => write!(f, "no such file or directory"),
+            Error::ReadOnly => write!(f, "read-only"),
+            Error::BadLinkTarget(target) => {
+                write!(f, "can not link to {}", target.display())
+            }
+            Error::BadName(name) => write!(f, "invalid name {:?}", name),
+            Error::NotEmpty => write!(f, "directory not empty"),
+            Error::SoundCloudError(err) => write!(f, "soundcloud: {}", err),
+            Error::IOError(err) => write!(f, "io: {}", err),
+            Error::ID3Error(err) => write!(f, "id3: {}", err),
+        }
     }
 }
 
@@ -47,7 +83,7 @@ impl error::Error for Error {}
 
 impl From<soundcloud::Error> for Error {
     fn from(err: soundcloud::Error) -> Error {
-        Error::SoundCloudError(err)
+        Error::SoundCloudError(Box::new(err))
     }
 }
 
@@ -63,14 +99,49 @@ impl From<id3::Error> for Error {
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaveformFiles {
+    None,
+    /// The samples as a JSON array of numbers between 0 and 1.
+    Json,
+    /// The image that is shown on the website.
+    Png,
+}
+
 // TODO: Use proper lifetimes to share state and make this private.
-#[derive(Clone)]
 pub struct RootState {
     pub sc_client: soundcloud::Client,
-    pub show: Vec<String>,
+    /// The account the client is logged in as. Its directory can be changed.
+    pub me: Option<soundcloud::User>,
+    /// The users that are recursed into. Users can be added and removed while mounted.
+    pub show: Mutex<Vec<String>>,
+    /// Where the users that are recursed into are kept for the next mount.
+    pub state_file: Option<PathBuf>,
     pub mpeg_padding: bool,
     pub id3_download_images: bool,
-    pub id3_parse_strings: bool,
+    /// The maximum width and height of artwork.
+    pub artwork_size: u32,
+    /// Expose the artwork of tracks as image files next to the audio.
+    pub artwork_files: bool,
+    /// Expose the track objects returned by the API as JSON files next to the audio.
+    pub json_files: bool,
+    pub waveform_files: WaveformFiles,
+    /// The rules used to extract metadata from track titles.
+    pub title_rules: TitleRules,
+    pub chapters: ChapterMode,
+    /// Append an ID3v1 tag to audio files.
+    pub id3v1: bool,
+    /// The comments of tracks that have been fetched during this mount, by track id.
+    pub comments: Mutex<HashMap<i64, Vec<soundcloud::Comment>>>,
+    /// Expose the timed comments of tracks as text files next to the audio.
+    pub comment_files: bool,
+    /// Write timed comments to the ID3 tag. The comments are fetched when the file is first
+    /// opened, which changes its size.
+    pub id3_comments: bool,
+    /// Report the size of audio streams that have been opened before from their actual length
+    /// instead of estimating it from the track duration.
+    pub exact_size: bool,
+    pub cache: Cache,
 }
 
 #[derive(Clone)]
@@ -86,9 +157,9 @@ impl<'a> Root<'a> {
 
 impl<'a> filesystem::NodeType for Root<'a> {
     type Error = Error;
-    type File = TrackAudio<'a>;
+    type File = File<'a>;
     type Directory = Dir<'a>;
-    type Symlink = UserReference;
+    type Symlink = Link;
 
     fn root(&self) -> Self::Directory {
         Dir::UserList(UserList { inner: &self.inner })
@@ -105,6 +176,9 @@ pub enum Dir<'a> {
     UserProfile(UserProfile<'a>),
     UserFavorites(UserFavorites<'a>),
     UserFollowing(UserFollowing<'a>),
+    UserPlaylists(UserPlaylists<'a>),
+    Playlist(Playlist<'a>),
+    Control(ControlDir<'a>),
 }
 
 impl filesystem::Meta for Dir<'_> {
@@ -115,6 +189,21 @@ impl filesystem::Meta for Dir<'_> {
             Dir::UserProfile(f) => f.metadata(),
             Dir::UserFavorites(f) => f.metadata(),
             Dir::UserFollowing(f) => f.metadata(),
+            Dir::UserPlaylists(f) => f.metadata(),
+            Dir::Playlist(f) => f.metadata(),
+            Dir::Control(f) => f.metadata(),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.xattrs(),
+            Dir::UserProfile(f) => f.xattrs(),
+            Dir::UserFavorites(f) => f.xattrs(),
+            Dir::UserFollowing(f) => f.xattrs(),
+            Dir::UserPlaylists(f) => f.xattrs(),
+            Dir::Playlist(f) => f.xattrs(),
+            Dir::Control(f) => f.xattrs(),
         }
     }
 }
@@ -126,11 +215,16 @@ impl<'a> filesystem::Directory<Root<'a>> for Dir<'a> {
             Dir::UserProfile(f) => f.files(),
             Dir::UserFavorites(f) => f.files(),
             Dir::UserFollowing(f) => f.files(),
+            Dir::UserPlaylists(f) => f.files(),
+            Dir::Playlist(f) => f.files(),
+            Dir::Control(f) => f.files(),
         }
     }
 
     fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
-        if !is_valid_file(name) {
+        // The control directory is the only hidden entry.
+        let is_control = matches!(self, Dir::UserList(_)) && name == CONTROL_DIR;
+        if !is_valid_file(name) && !is_control {
             return Err(Error::ChildNotFound);
         }
         match self {
@@ -138,6 +232,132 @@ impl<'a> filesystem::Directory<Root<'a>> for Dir<'a> {
             Dir::UserProfile(f) => f.file_by_name(name),
             Dir::UserFavorites(f) => f.file_by_name(name),
             Dir::UserFollowing(f) => f.file_by_name(name),
+            Dir::UserPlaylists(f) => f.file_by_name(name),
+            Dir::Playlist(f) => f.file_by_name(name),
+            Dir::Control(f) => f.file_by_name(name),
+        }
+    }
+
+    fn symlink(
+        &self,
+        name: &str,
+        target: &Path,
+    ) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        match self {
+            Dir::UserFavorites(f) => f.symlink(name, target),
+            Dir::UserFollowing(f) => f.symlink(name, target),
+            Dir::Playlist(f) => f.symlink(name, target),
+            _ => Err(Error::ReadOnly),
+        }
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        match self {
+            Dir::UserFavorites(f) => f.unlink(name),
+            Dir::UserFollowing(f) => f.unlink(name),
+            Dir::Playlist(f) => f.unlink(name),
+            _ => Err(Error::ReadOnly),
+        }
+    }
+
+    fn mkdir(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        match self {
+            Dir::UserList(f) => f.mkdir(name),
+            Dir::UserPlaylists(f) => f.mkdir(name),
+            _ => Err(Error::ReadOnly),
+        }
+    }
+
+    fn rmdir(&self, name: &str) -> Result<(), Self::Error> {
+        match self {
+            Dir::UserList(f) => f.rmdir(name),
+            Dir::UserPlaylists(f) => f.rmdir(name),
+            _ => Err(Error::ReadOnly),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum File<'a> {
+    TrackAudio(TrackAudio<'a>),
+    CueSheet(CueSheet<'a>),
+    Image(Image<'a>),
+    TrackJson(TrackJson),
+    Waveform(Waveform<'a>),
+    Comments(Comments<'a>),
+    Control(Control<'a>),
+}
+
+impl filesystem::Meta for File<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        match self {
+            File::TrackAudio(f) => f.metadata(),
+            File::CueSheet(f) => f.metadata(),
+            File::Image(f) => f.metadata(),
+            File::TrackJson(f) => f.metadata(),
+            File::Waveform(f) => f.metadata(),
+            File::Comments(f) => f.metadata(),
+            File::Control(f) => f.metadata(),
+        }
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        match self {
+            File::TrackAudio(f) => f.identity(),
+            File::CueSheet(f) => f.identity(),
+            File::Image(f) => f.identity(),
+            File::TrackJson(f) => f.identity(),
+            File::Waveform(f) => f.identity(),
+            File::Comments(f) => f.identity(),
+            File::Control(f) => f.identity(),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        match self {
+            File::TrackAudio(f) => f.xattrs(),
+            File::CueSheet(f) => f.xattrs(),
+            File::Image(f) => f.xattrs(),
+            File::TrackJson(f) => f.xattrs(),
+            File::Waveform(f) => f.xattrs(),
+            File::Comments(f) => f.xattrs(),
+            File::Control(f) => f.xattrs(),
+        }
+    }
+}
+
+impl<'a> filesystem::File for File<'a> {
+    type Reader = Box<dyn ReadSeek + 'a>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        match self {
+            File::TrackAudio(f) => Ok(Box::new(f.open_ro()?)),
+            File::CueSheet(f) => Ok(Box::new(f.open_ro()?)),
+            File::Image(f) => Ok(Box::new(f.open_ro()?)),
+            File::TrackJson(f) => Ok(Box::new(f.open_ro()?)),
+            File::Waveform(f) => Ok(Box::new(f.open_ro()?)),
+            File::Comments(f) => Ok(Box::new(f.open_ro()?)),
+            File::Control(f) => Ok(Box::new(f.open_ro()?)),
+        }
+    }
+
+    fn size(&self) -> Result<u64, Self::Error> {
+        match self {
+            File::TrackAudio(f) => f.size(),
+            File::CueSheet(f) => f.size(),
+            File::Image(f) => f.size(),
+            File::TrackJson(f) => f.size(),
+            File::Waveform(f) => f.size(),
+            File::Comments(f) => f.size(),
+            File::Control(f) => f.size(),
+        }
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(), Self::Error> {
+        match self {
+            File::Control(f) => f.write(data),
+            _ => Err(Error::ReadOnly),
         }
     }
 }
@@ -154,38 +374,132 @@ impl filesystem::Meta for UserList<'_> {
         Ok(filesystem::Metadata {
             mtime: now,
             ctime: now,
-            perm: 0o555,
+            perm: 0o755,
         })
     }
 }
 
+impl<'a> UserList<'a> {
+    fn control(&self) -> filesystem::Node<Root<'a>> {
+        filesystem::Node::Directory(Dir::Control(ControlDir { inner: self.inner }))
+    }
+
+    /// The directory of the logged in account, which is always recursed into.
+    fn me(&self) -> Option<filesystem::Node<Root<'a>>> {
+        let me = self.inner.me.as_ref()?;
+        Some(filesystem::Node::Directory(Dir::UserProfile(UserProfile {
+            inner: self.inner,
+            user: me.clone(),
+            recurse: true,
+        })))
+    }
+}
+
 impl<'a> filesystem::Directory<Root<'a>> for UserList<'a> {
     fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
-        self.inner
-            .show
-            .iter()
-            .map(|name| {
-                let entry = filesystem::Node::Directory(Dir::UserProfile(UserProfile {
-                    inner: &self.inner,
-                    user: soundcloud::User::by_name(&self.inner.sc_client, name)?,
-                    recurse: true,
-                }));
-                Ok((name.clone(), entry))
-            })
-            .collect()
+        let mut files = vec![(CONTROL_DIR.to_string(), self.control())];
+        files.extend(self.me().map(|me| ("me".to_string(), me)));
+        let show = self.inner.show.lock().unwrap().clone();
+        for name in show {
+            let entry = filesystem::Node::Directory(Dir::UserProfile(UserProfile {
+                inner: self.inner,
+                user: soundcloud::User::by_name(&self.inner.sc_client, &name)?,
+                recurse: true,
+            }));
+            files.push((name, entry));
+        }
+        Ok(files)
     }
 
     fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        if name == CONTROL_DIR {
+            return Ok(self.control());
+        }
         if name.contains('.') {
             return Err(Error::ChildNotFound);
         }
+        if name == "me" {
+            return self.me().ok_or(Error::ChildNotFound);
+        }
+        // The logged in account shares its directory with "me". Other users only have a
+        // directory while they are recursed into, so that creating one for a user reaches `mkdir`
+        // instead of failing because it already exists. Links to other users dangle until then.
+        let is_me = self
+            .inner
+            .me
+            .as_ref()
+            .is_some_and(|me| me.permalink == name);
+        if !is_me && !is_shown(self.inner, name) {
+            return Err(Error::ChildNotFound);
+        }
         let entry = filesystem::Node::Directory(Dir::UserProfile(UserProfile {
-            inner: &self.inner,
+            inner: self.inner,
             user: soundcloud::User::by_name(&self.inner.sc_client, name)?,
-            recurse: self.inner.show.iter().any(|n| n == name),
+            recurse: true,
         }));
         Ok(entry)
     }
+
+    /// Creating a directory for a user recurses into them.
+    fn mkdir(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        if name.contains('.') || name == "me" {
+            return Err(Error::ReadOnly);
+        }
+        let user = match soundcloud::User::by_name(&self.inner.sc_client, name) {
+            Ok(user) => user,
+            Err(err) if err.is_not_found() => return Err(Error::ChildNotFound),
+            Err(err) => return Err(err.into()),
+        };
+        show_user(self.inner, &user.permalink)?;
+        Ok(filesystem::Node::Directory(Dir::UserProfile(UserProfile {
+            inner: self.inner,
+            user,
+            recurse: true,
+        })))
+    }
+
+    /// Removing the directory of a user stops recursing into them. Nothing is deleted.
+    fn rmdir(&self, name: &str) -> Result<(), Self::Error> {
+        if name == CONTROL_DIR || name == "me" {
+            return Err(Error::ReadOnly);
+        }
+        hide_user(self.inner, name)
+    }
+}
+
+fn is_shown(inner: &RootState, name: &str) -> bool {
+    inner.show.lock().unwrap().iter().any(|n| n == name)
+}
+
+/// Starts recursing into a user, remembering that for the next mount if there is a state file.
+fn show_user(inner: &RootState, permalink: &str) -> Result<(), Error> {
+    let mut show = inner.show.lock().unwrap();
+    if show.iter().any(|n| n == permalink) {
+        return Ok(());
+    }
+    info!("adding user {}", permalink);
+    show.push(permalink.to_string());
+    save_show(inner, &show)
+}
+
+fn hide_user(inner: &RootState, name: &str) -> Result<(), Error> {
+    let mut show = inner.show.lock().unwrap();
+    let len = show.len();
+    show.retain(|n| n != name);
+    if show.len() == len {
+        return Err(Error::ChildNotFound);
+    }
+    info!("removing user {}", name);
+    save_show(inner, &show)
+}
+
+fn save_show(inner: &RootState, show: &[String]) -> Result<(), Error> {
+    // Directories that have been listed before need to pick up the change.
+    filesystem::invalidate_all();
+    if let Some(path) = &inner.state_file {
+        state::save_users(path, show)?;
+    }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -200,29 +514,106 @@ impl filesystem::Meta for UserFavorites<'_> {
         Ok(filesystem::Metadata {
             mtime: self.user.last_modified,
             ctime: self.user.last_modified,
-            perm: 0o555,
+            perm: writable_perm(self.inner, self.user.id),
         })
     }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(user_xattrs(&self.user))
+    }
 }
 
 impl<'a> filesystem::Directory<Root<'a>> for UserFavorites<'a> {
     fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
-        let files: Vec<_> = self
+        let files = self
             .user
             .favorites(&self.inner.sc_client)?
             .into_iter()
-            .map(|track| {
-                (
-                    format!("{}_-_{}.mp3", track.user.permalink, track.permalink),
-                    filesystem::Node::File(TrackAudio {
-                        inner: self.inner,
-                        track,
-                    }),
-                )
+            .flat_map(|track| {
+                let name = format!("{}_-_{}", track.user.permalink, track.permalink);
+                track_files(self.inner, name, track)
             })
             .collect();
         Ok(files)
     }
+
+    /// Liking a track is done by linking to its audio file. The link stands in for the track
+    /// until the directory is listed again.
+    fn symlink(
+        &self,
+        _name: &str,
+        target: &Path,
+    ) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        writable(self.inner, self.user.id)?;
+        let track = linked_track(self.inner, target)?;
+        info!("liking track {}", track.permalink_url);
+        self.inner.sc_client.like(&track)?;
+        Ok(filesystem::Node::Symlink(Link::Track(TrackReference {
+            track,
+            depth: 2,
+        })))
+    }
+
+    /// Unliking a track is done by removing its audio file. The other files of the track go with
+    /// it.
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        writable(self.inner, self.user.id)?;
+        let track = match self.file_by_name(name)? {
+            filesystem::Node::File(File::TrackAudio(audio)) => audio.track,
+            _ => return Err(Error::ReadOnly),
+        };
+        info!("unliking track {}", track.permalink_url);
+        self.inner.sc_client.unlike(&track)?;
+        Ok(())
+    }
+}
+
+/// Only the directories of the logged in account can be changed.
+fn writable(inner: &RootState, owner_id: i64) -> Result<(), Error> {
+    match &inner.me {
+        Some(me) if me.id == owner_id => Ok(()),
+        _ => Err(Error::ReadOnly),
+    }
+}
+
+fn writable_perm(inner: &RootState, owner_id: i64) -> u16 {
+    if writable(inner, owner_id).is_ok() {
+        0o755
+    } else {
+        0o555
+    }
+}
+
+/// Finds the track that the target of a symlink refers to. Audio files are named `<track>.mp3` in
+/// the directory of their user and `<user>_-_<track>.mp3` in favorites and sets.
+///
+/// The directory of a set has an arbitrary name, and a relative target does not tell whether it
+/// is in a set. Names of the second form are therefore tried both ways.
+fn linked_track(inner: &RootState, target: &Path) -> Result<soundcloud::Track, Error> {
+    let bad_target = || Error::BadLinkTarget(target.to_path_buf());
+    let mut components = target.iter().rev().map(|c| c.to_str());
+    let file = components.next().flatten().ok_or_else(bad_target)?;
+    let name = file.strip_suffix(".mp3").ok_or_else(bad_target)?;
+    let dir = components.next().flatten().ok_or_else(bad_target)?;
+
+    let mut candidates = Vec::new();
+    candidates.extend(name.split_once("_-_"));
+    match dir {
+        "favorites" => (),
+        "me" => {
+            let me = inner.me.as_ref().ok_or_else(bad_target)?;
+            candidates.push((me.permalink.as_str(), name));
+        }
+        user_pl => candidates.push((user_pl, name)),
+    }
+    let mut err = bad_target();
+    for (user_pl, track_pl) in candidates {
+        match soundcloud::Track::by_permalink(&inner.sc_client, user_pl, track_pl) {
+            Ok(track) => return Ok(track),
+            Err(e) => err = e.into(),
+        }
+    }
+    Err(err)
 }
 
 #[derive(Clone)]
@@ -237,9 +628,13 @@ impl filesystem::Meta for UserFollowing<'_> {
         Ok(filesystem::Metadata {
             mtime: self.user.last_modified,
             ctime: self.user.last_modified,
-            perm: 0o555,
+            perm: writable_perm(self.inner, self.user.id),
         })
     }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(user_xattrs(&self.user))
+    }
 }
 
 impl<'a> filesystem::Directory<Root<'a>> for UserFollowing<'a> {
@@ -251,12 +646,218 @@ impl<'a> filesystem::Directory<Root<'a>> for UserFollowing<'a> {
             .map(|user| {
                 (
                     user.permalink.clone(),
-                    filesystem::Node::Symlink(UserReference { user }),
+                    filesystem::Node::Symlink(Link::User(UserReference { user })),
+                )
+            })
+            .collect();
+        Ok(files)
+    }
+
+    /// Following a user is done by linking to their directory.
+    fn symlink(
+        &self,
+        _name: &str,
+        target: &Path,
+    ) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        writable(self.inner, self.user.id)?;
+        let user_pl = target
+            .file_name()
+            .and_then(|name| name.to_str())
+            .filter(|name| !name.contains('.'))
+            .ok_or_else(|| Error::BadLinkTarget(target.to_path_buf()))?;
+        let user = soundcloud::User::by_name(&self.inner.sc_client, user_pl)?;
+        info!("following user {}", user.permalink_url);
+        self.inner.sc_client.follow(&user)?;
+        Ok(filesystem::Node::Symlink(Link::User(UserReference {
+            user,
+        })))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        writable(self.inner, self.user.id)?;
+        let user = match self.file_by_name(name)? {
+            filesystem::Node::Symlink(Link::User(reference)) => reference.user,
+            _ => return Err(Error::ReadOnly),
+        };
+        info!("unfollowing user {}", user.permalink_url);
+        self.inner.sc_client.unfollow(&user)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct UserPlaylists<'a> {
+    inner: &'a RootState,
+    user: soundcloud::User,
+}
+
+impl filesystem::Meta for UserPlaylists<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.user.last_modified,
+            ctime: self.user.last_modified,
+            perm: writable_perm(self.inner, self.user.id),
+        })
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(user_xattrs(&self.user))
+    }
+}
+
+impl<'a> filesystem::Directory<Root<'a>> for UserPlaylists<'a> {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
+        let files = self
+            .user
+            .playlists(&self.inner.sc_client)?
+            .into_iter()
+            .map(|playlist| {
+                let dir = Playlist {
+                    inner: self.inner,
+                    playlist,
+                };
+                (
+                    dir.playlist.permalink.clone(),
+                    filesystem::Node::Directory(Dir::Playlist(dir)),
                 )
             })
             .collect();
         Ok(files)
     }
+
+    /// Sets are listed by their permalink, so the name is used as both the title and the permalink
+    /// of the new set.
+    fn mkdir(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        writable(self.inner, self.user.id)?;
+        let is_permalink = name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if name.is_empty() || !is_permalink {
+            return Err(Error::BadName(name.to_string()));
+        }
+        info!("creating set {}", name);
+        let playlist = soundcloud::Playlist::create(&self.inner.sc_client, name, name)?;
+        if playlist.permalink != name {
+            warn!("set {} was created as {}", name, playlist.permalink_url);
+        }
+        Ok(filesystem::Node::Directory(Dir::Playlist(Playlist {
+            inner: self.inner,
+            playlist,
+        })))
+    }
+
+    /// Sets are only deleted once their tracks have been removed, just like directories. The
+    /// artwork and sidecar files are generated from the set and its tracks, so they do not keep a
+    /// set from being deleted.
+    fn rmdir(&self, name: &str) -> Result<(), Self::Error> {
+        writable(self.inner, self.user.id)?;
+        let playlist = match self.file_by_name(name)? {
+            filesystem::Node::Directory(Dir::Playlist(dir)) => dir.current()?,
+            _ => return Err(Error::ChildNotFound),
+        };
+        if !playlist.tracks.is_empty() {
+            return Err(Error::NotEmpty);
+        }
+        info!("deleting set {}", playlist.permalink_url);
+        playlist.delete(&self.inner.sc_client)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Playlist<'a> {
+    inner: &'a RootState,
+    playlist: soundcloud::Playlist,
+}
+
+impl Playlist<'_> {
+    /// The set as it is now. Sets are fetched again before changing them so concurrent changes are
+    /// not undone.
+    fn current(&self) -> Result<soundcloud::Playlist, Error> {
+        Ok(soundcloud::Playlist::by_id(
+            &self.inner.sc_client,
+            self.playlist.id,
+        )?)
+    }
+}
+
+impl filesystem::Meta for Playlist<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.playlist.last_modified,
+            ctime: self.playlist.created_at,
+            perm: writable_perm(self.inner, self.playlist.user_id),
+        })
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(vec![
+            xattr("playlist_id", self.playlist.id),
+            xattr("title", &self.playlist.title),
+            xattr("url", &self.playlist.permalink_url),
+        ])
+    }
+}
+
+impl<'a> filesystem::Directory<Root<'a>> for Playlist<'a> {
+    /// The tracks are listed in the order of the set, after the artwork of the set if it has any.
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
+        let playlist = self.current()?;
+        let mut files = Vec::new();
+        if playlist.has_artwork() {
+            let cover = filesystem::Node::File(File::Image(Image {
+                inner: self.inner,
+                source: ImageSource::PlaylistArtwork(Box::new(playlist.clone())),
+            }));
+            files.push(("cover.jpg".to_string(), cover));
+        }
+        files.extend(playlist.tracks.into_iter().flat_map(|track| {
+            let name = format!("{}_-_{}", track.user.permalink, track.permalink);
+            track_files(self.inner, name, track)
+        }));
+        Ok(files)
+    }
+
+    /// Linking to an audio file appends the track to the set.
+    fn symlink(
+        &self,
+        _name: &str,
+        target: &Path,
+    ) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
+        writable(self.inner, self.playlist.user_id)?;
+        let track = linked_track(self.inner, target)?;
+        let playlist = self.current()?;
+        let mut track_ids = playlist.track_ids();
+        track_ids.push(track.id);
+        info!(
+            "adding track {} to set {}",
+            track.permalink_url, playlist.permalink_url
+        );
+        playlist.set_tracks(&self.inner.sc_client, &track_ids)?;
+        Ok(filesystem::Node::Symlink(Link::Track(TrackReference {
+            track,
+            depth: 3,
+        })))
+    }
+
+    fn unlink(&self, name: &str) -> Result<(), Self::Error> {
+        writable(self.inner, self.playlist.user_id)?;
+        let track = match self.file_by_name(name)? {
+            filesystem::Node::File(File::TrackAudio(audio)) => audio.track,
+            _ => return Err(Error::ReadOnly),
+        };
+        let playlist = self.current()?;
+        let mut track_ids = playlist.track_ids();
+        track_ids.retain(|&id| id != track.id);
+        info!(
+            "removing track {} from set {}",
+            track.permalink_url, playlist.permalink_url
+        );
+        playlist.set_tracks(&self.inner.sc_client, &track_ids)?;
+        Ok(())
+    }
 }
 
 #[derive(Clone)]
@@ -264,10 +865,22 @@ pub struct UserProfile<'a> {
     inner: &'a RootState,
     user: soundcloud::User,
     // Only add child directories for users marked for recursing, to prevent recursing too deeply.
+    // Users can also be marked while mounted, which is checked by `recurses`.
     recurse: bool,
 }
 
 impl<'a> UserProfile<'a> {
+    fn recurses(&self) -> bool {
+        self.recurse || is_shown(self.inner, &self.user.permalink)
+    }
+
+    fn cover(&self) -> filesystem::Node<Root<'a>> {
+        filesystem::Node::File(File::Image(Image {
+            inner: self.inner,
+            source: ImageSource::UserAvatar(Box::new(self.user.clone())),
+        }))
+    }
+
     fn favorites(&self) -> filesystem::Node<Root<'a>> {
         filesystem::Node::Directory(Dir::UserFavorites(UserFavorites {
             user: self.user.clone(),
@@ -281,6 +894,13 @@ impl<'a> UserProfile<'a> {
             user: self.user.clone(),
         }))
     }
+
+    fn playlists(&self) -> filesystem::Node<Root<'a>> {
+        filesystem::Node::Directory(Dir::UserPlaylists(UserPlaylists {
+            inner: self.inner,
+            user: self.user.clone(),
+        }))
+    }
 }
 
 impl filesystem::Meta for UserProfile<'_> {
@@ -292,47 +912,138 @@ impl filesystem::Meta for UserProfile<'_> {
             perm: 0o555,
         })
     }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(user_xattrs(&self.user))
+    }
 }
 
 impl<'a> filesystem::Directory<Root<'a>> for UserProfile<'a> {
     fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
-        let mut files = Vec::new();
-        if self.recurse {
+        let mut files = vec![("cover.jpg".to_string(), self.cover())];
+        if self.recurses() {
             files.push(("favorites".to_string(), self.favorites()));
             files.push(("following".to_string(), self.following()));
+            files.push(("playlists".to_string(), self.playlists()));
         }
         let tracks = self
             .user
             .tracks(&self.inner.sc_client)?
             .into_iter()
-            .map(|track| {
-                (
-                    format!("{}.mp3", track.permalink),
-                    filesystem::Node::File(TrackAudio {
-                        inner: self.inner,
-                        track,
-                    }),
-                )
-            });
+            .flat_map(|track| track_files(self.inner, track.permalink.clone(), track));
         files.extend(tracks);
         Ok(files)
     }
 
     fn file_by_name(&self, name: &str) -> Result<filesystem::Node<Root<'a>>, Self::Error> {
         match name {
+            "cover.jpg" => return Ok(self.cover()),
             "favorites" => return Ok(self.favorites()),
             "following" => return Ok(self.following()),
+            "playlists" => return Ok(self.playlists()),
             _ => (),
         }
 
-        let track_pl = name.trim_end_matches(".mp3");
+        let (track_pl, ext) = TRACK_FILE_EXTENSIONS
+            .iter()
+            .find_map(|&ext| Some((name.strip_suffix(ext)?, ext)))
+            .unwrap_or((name, ".mp3"));
         let track =
             soundcloud::Track::by_permalink(&self.inner.sc_client, &self.user.permalink, track_pl)?;
-        Ok(filesystem::Node::File(TrackAudio {
-            inner: self.inner,
-            track,
-        }))
+        if ext == ".mp3" {
+            return Ok(filesystem::Node::File(File::TrackAudio(TrackAudio {
+                inner: self.inner,
+                track,
+            })));
+        }
+        // Sidecar files may or may not exist depending on the track and the options.
+        track_files(self.inner, track_pl.to_string(), track)
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, node)| node)
+            .ok_or(Error::ChildNotFound)
+    }
+}
+
+/// The extensions of the files returned by `track_files`. Extensions that end in another one come
+/// first.
+const TRACK_FILE_EXTENSIONS: &[&str] = &[
+    ".waveform.json",
+    ".waveform.png",
+    ".comments.txt",
+    ".json",
+    ".jpg",
+    ".cue",
+    ".mp3",
+];
+
+/// The files that represent a track: the audio and its sidecar files. The name is without
+/// extension.
+fn track_files<'a>(
+    inner: &'a RootState,
+    name: String,
+    track: soundcloud::Track,
+) -> Vec<(String, filesystem::Node<Root<'a>>)> {
+    let audio_name = format!("{}.mp3", name);
+    let mut files = Vec::new();
+    if inner.chapters == ChapterMode::Cue && !chapters::from_track(&track, 0).is_empty() {
+        files.push((
+            format!("{}.cue", name),
+            filesystem::Node::File(File::CueSheet(CueSheet {
+                inner,
+                track: track.clone(),
+                audio_name: audio_name.clone(),
+            })),
+        ));
+    }
+    if inner.artwork_files && track.has_artwork() {
+        files.push((
+            format!("{}.jpg", name),
+            filesystem::Node::File(File::Image(Image {
+                inner,
+                source: ImageSource::TrackArtwork(Box::new(track.clone())),
+            })),
+        ));
+    }
+    if inner.json_files {
+        files.push((
+            format!("{}.json", name),
+            filesystem::Node::File(File::TrackJson(TrackJson {
+                track: track.clone(),
+            })),
+        ));
     }
+    if track.waveform_url.is_some() {
+        match inner.waveform_files {
+            WaveformFiles::None => (),
+            WaveformFiles::Json => files.push((
+                format!("{}.waveform.json", name),
+                filesystem::Node::File(File::Waveform(Waveform {
+                    inner,
+                    track: track.clone(),
+                })),
+            )),
+            WaveformFiles::Png => files.push((
+                format!("{}.waveform.png", name),
+                filesystem::Node::File(File::Image(Image {
+                    inner,
+                    source: ImageSource::TrackWaveform(Box::new(track.clone())),
+                })),
+            )),
+        }
+    }
+    if inner.comment_files && track.commentable && track.comment_count != Some(0) {
+        files.push((
+            format!("{}.comments.txt", name),
+            filesystem::Node::File(File::Comments(Comments {
+                inner,
+                track: track.clone(),
+            })),
+        ));
+    }
+    let audio = TrackAudio { inner, track };
+    files.push((audio_name, filesystem::Node::File(File::TrackAudio(audio))));
+    files
 }
 
 #[derive(Clone)]
@@ -350,97 +1061,953 @@ impl filesystem::Meta for TrackAudio<'_> {
             perm: 0o444,
         })
     }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "track",
+            id: self.track.id,
+        })
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        let track = &self.track;
+        let attrs = vec![
+            xattr("track_id", track.id),
+            xattr("permalink_url", &track.permalink_url),
+            xattr("license", &track.license),
+        ];
+        let optional_attrs = iter::empty()
+            .chain(track.genre.as_ref().map(|v| xattr("genre", v)))
+            .chain(track.bpm.map(|v| xattr("bpm", v)))
+            .chain(track.likes_count.map(|v| xattr("likes", v)))
+            .chain(track.playback_count.map(|v| xattr("playback_count", v)))
+            .chain(
+                track
+                    .waveform_url
+                    .as_ref()
+                    .map(|v| xattr("waveform_url", v)),
+            );
+        Ok(attrs.into_iter().chain(optional_attrs).collect())
+    }
 }
 
 impl<'a> filesystem::File for TrackAudio<'a> {
-    type Reader = Concat<Box<dyn ReadSeek + 'a>>;
+    type Reader = Fit<Concat<Box<dyn ReadSeek + 'a>>, [u8; 1]>;
 
+    /// The file is laid out from the start of the stream, which is read the first time a file is
+    /// opened, and from the comments, which are fetched then. Either may change the size. The
+    /// kernel holds on to the size it was told before, so the file is fitted to that size until
+    /// it is asked again.
     fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        let announced_size = self.size()?;
+        let stream = self.probe_stream()?;
+        let artwork = if self.inner.id3_download_images {
+            self.artwork()
+        } else {
+            None
+        };
         let id3_tag = tag_for_track(
             &self.track,
-            self.inner.id3_download_images,
-            self.inner.id3_parse_strings,
+            self.artwork_slot(artwork.as_deref()),
+            &self.inner.title_rules,
+            &self.chapters(&stream),
+            &self.comments(&stream, true),
         )?;
 
-        let remote_mp3_size = self.track.audio_size() as u64;
-        let padding_len = mp3::ZERO_FRAME.len() as u64;
-        let mp3_total_size =
-            remote_mp3_size + PADDING_START * padding_len + PADDING_END * padding_len;
-        let mp3_header = mp3::cbr_header(mp3_total_size);
-        let first_frame_size = mp3_header.len() as u64;
+        let format = stream.format;
+        let (audio_frames, remote_mp3_size) = self.audio_len(&stream);
+        let padding_len = mp3::zero_frame(&format).len() as u64;
+        let padding_frames = if self.inner.mpeg_padding {
+            PADDING_START + PADDING_END
+        } else {
+            0
+        };
+        let mp3_total_size = remote_mp3_size + padding_frames * padding_len;
+        // The Info frame is written in both layouts, it is what tells players how to play the
+        // stream without gaps.
+        let mp3_header = mp3::cbr_header(
+            &format,
+            padding_frames + audio_frames,
+            mp3_total_size,
+            &self.lame_tag(&stream, audio_frames),
+        );
 
         // Hackety hack: the file concatenation abstraction is able to lazily index the
         // size of the underlying files. This ensures for programs that just want to probe
         // the audio file's metadata, no request for the actual audio file will be
-        // performed.
+        // performed, apart from reading the start of the stream the first time it is opened.
         // However, because reading programs may read beyond the metadata, the audio may
         // still be accessed. To counter this, we jam a very large swath of zero bytes in
         // between the metadata and audio stream to saturate the read buffer without the
         // audio stream.
-        let padding_start = mp3::zero_frames(PADDING_START);
+        let padding_start = mp3::zero_frames(&format, PADDING_START);
         // We also need some padding at the end for players that try to
         // read ID3v1 metadata.
-        let padding_end = mp3::zero_frames(PADDING_END);
+        let padding_end = mp3::zero_frames(&format, PADDING_END);
 
         let track_cp = self.track.clone();
-        let sc_client_cp = &self.inner.sc_client;
+        let inner = self.inner;
         let audio = LazyOpen::with_size_hint(remote_mp3_size, move || {
             let f = track_cp
-                .audio(sc_client_cp)
+                .audio(&inner.sc_client)
                 .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{}", err)))?;
-            Ok(Skip::new(f, first_frame_size))
+            // The ID3 tag and Info frame of the upstream are replaced by our own.
+            Ok(Skip::new(f, stream.audio_offset()))
         });
+        // The size of the audio may not have been known up front. Make sure that the size of the
+        // file always matches the size that was announced.
+        let audio = Fit::new(audio, remote_mp3_size, mp3::zero_frame(&format));
 
-        let concat = if self.inner.mpeg_padding {
-            Concat::new(vec![
+        let mut segments = if self.inner.mpeg_padding {
+            vec![
                 Box::<dyn ReadSeek>::from(Box::new(id3_tag)),
                 Box::<dyn ReadSeek>::from(Box::new(io::Cursor::new(mp3_header))),
                 Box::<dyn ReadSeek>::from(Box::new(padding_start)),
                 Box::<dyn ReadSeek>::from(Box::new(audio)),
                 Box::<dyn ReadSeek>::from(Box::new(padding_end)),
-            ])
+            ]
         } else {
-            Concat::new(vec![
+            vec![
                 Box::<dyn ReadSeek>::from(Box::new(id3_tag)),
+                Box::<dyn ReadSeek>::from(Box::new(io::Cursor::new(mp3_header))),
                 Box::<dyn ReadSeek>::from(Box::new(audio)),
-            ])
+            ]
         };
-        Ok(concat)
+        if self.inner.id3v1 {
+            let id3v1_tag = id3v1_for_track(&self.track, &self.inner.title_rules);
+            segments.push(Box::new(io::Cursor::new(id3v1_tag)));
+        }
+        Ok(Fit::new(Concat::new(segments), announced_size, [0]))
     }
 
     fn size(&self) -> Result<u64, Self::Error> {
+        let stream = self.stream();
         let id3_tag_size = {
+            // The artwork is not needed to know the size of its slot.
             let mut b = tag_for_track(
                 &self.track,
-                self.inner.id3_download_images,
-                self.inner.id3_parse_strings,
+                self.artwork_slot(None),
+                &self.inner.title_rules,
+                &self.chapters(&stream),
+                &self.comments(&stream, false),
             )?;
             b.seek(io::SeekFrom::End(0)).unwrap()
         };
+        let format = stream.format;
+        let header_len = mp3::cbr_header(&format, 0, 0, &mp3::LameTag::new(0, 0)).len() as u64;
         let padding_size = if self.inner.mpeg_padding {
-            let padding_len = mp3::ZERO_FRAME.len() as u64;
+            let padding_len = mp3::zero_frame(&format).len() as u64;
             PADDING_START * padding_len + PADDING_END * padding_len
         } else {
             0
         };
-        Ok(id3_tag_size + padding_size + self.track.audio_size() as u64)
+        let (_, audio_size) = self.audio_len(&stream);
+        let id3v1_size = if self.inner.id3v1 {
+            ID3V1_LEN as u64
+        } else {
+            0
+        };
+        Ok(id3_tag_size + header_len + padding_size + audio_size + id3v1_size)
     }
 }
 
-#[derive(Clone)]
-pub struct UserReference {
-    user: soundcloud::User,
-}
+impl TrackAudio<'_> {
+    /// The start of the audio stream as it was seen when the stream was first opened. Streams that
+    /// have not been opened yet are assumed to start with a single Info frame in the default
+    /// format, so the size of a file may change when it is first opened.
+    fn stream(&self) -> mp3::StreamPrefix {
+        stream_prefix(self.inner, self.track.id).unwrap_or(mp3::StreamPrefix {
+            format: mp3::DEFAULT_FORMAT,
+            id3_len: 0,
+            info_frame_len: mp3::DEFAULT_FORMAT.frame_len(),
+            lame: None,
+        })
+    }
 
-impl filesystem::Meta for UserReference {
-    type Error = Error;
-    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
-        Ok(filesystem::Metadata {
+    /// Like `stream`, but the start of the stream is read if it has not been seen before. This
+    /// makes sure that the file is laid out in the format of the audio.
+    fn probe_stream(&self) -> Result<mp3::StreamPrefix, Error> {
+        if let Some(prefix) = stream_prefix(self.inner, self.track.id) {
+            return Ok(prefix);
+        }
+        let mut f = self.track.audio(&self.inner.sc_client)?;
+        let prefix = mp3::read_stream_prefix(&mut f)?;
+        let id = self.track.id;
+        if prefix.format.bitrate != mp3::DEFAULT_FORMAT.bitrate
+            || prefix.format.sample_rate != mp3::DEFAULT_FORMAT.sample_rate
+        {
+            info!(
+                "track {} has {} bit/s at {} Hz",
+                id, prefix.format.bitrate, prefix.format.sample_rate
+            );
+        }
+        if prefix.id3_len > 0 {
+            debug!(
+                "track {} has an upstream ID3 tag of {} bytes",
+                id, prefix.id3_len
+            );
+        }
+        if let Some(lame) = &prefix.lame {
+            debug!(
+                "track {} was encoded by {}, delay: {}, padding: {}",
+                id,
+                String::from_utf8_lossy(lame.encoder()),
+                lame.delay(),
+                lame.padding()
+            );
+        }
+        self.inner
+            .cache
+            .put(&stream_prefix_key(id), &prefix.encode());
+        if self.inner.exact_size {
+            // The length is known from the response, so this does not perform a request.
+            let len = f.seek(io::SeekFrom::End(0))?;
+            self.inner
+                .cache
+                .put(&stream_length_key(id), len.to_string().as_bytes());
+        }
+        Ok(prefix)
+    }
+
+    fn artwork_slot<'b>(&self, image: Option<&'b [u8]>) -> Option<ArtworkSlot<'b>> {
+        if !self.inner.id3_download_images || !self.track.has_artwork() {
+            return None;
+        }
+        Some(ArtworkSlot::for_size(self.inner.artwork_size, image))
+    }
+
+    fn artwork(&self) -> Option<Vec<u8>> {
+        let key = artwork_key(self.track.id, self.inner.artwork_size);
+        cached_download(self.inner, &key, || {
+            self.track
+                .artwork(self.inner.artwork_size)
+                .map(|(data, _)| data)
+                .map_err(Box::new)
+        })
+    }
+
+    /// The chapters to write to the ID3 tag, if any.
+    fn chapters(&self, stream: &mp3::StreamPrefix) -> Vec<chapters::Chapter> {
+        if self.inner.chapters != ChapterMode::ID3 {
+            return Vec::new();
+        }
+        chapters::from_track(&self.track, audio_offset_ms(self.inner, &stream.format))
+    }
+
+    /// The timed comments to write to the ID3 tag, if any. Only comments that have been fetched
+    /// before are used unless fetch is set. The audio can still be played if the comments can not
+    /// be fetched.
+    fn comments(&self, stream: &mp3::StreamPrefix, fetch: bool) -> Vec<(u64, String)> {
+        if !self.inner.id3_comments {
+            return Vec::new();
+        }
+        let fetched = if fetch {
+            track_comments(self.inner, &self.track).unwrap_or_else(|err| {
+                warn!(
+                    "could not fetch comments of track {}: {}",
+                    self.track.id, err
+                );
+                Vec::new()
+            })
+        } else {
+            fetched_comments(self.inner, self.track.id).unwrap_or_default()
+        };
+        comments::timed(&fetched, audio_offset_ms(self.inner, &stream.format))
+    }
+
+    /// The gapless playback information for the Info frame. The LAME tag of the upstream is used
+    /// if it has one, otherwise the padding is derived from the duration of the track.
+    ///
+    /// The zero frames that are added by the MPEG padding option are too long to be described by
+    /// the tag, so players will play those as silence. Playback is only gapless without them.
+    fn lame_tag(&self, stream: &mp3::StreamPrefix, audio_frames: u64) -> mp3::LameTag {
+        if let Some(lame) = stream.lame {
+            return lame;
+        }
+        let samples = self.track.duration_ms as u64 * u64::from(stream.format.sample_rate) / 1000;
+        let padding = (audio_frames * stream.format.samples_per_frame()).saturating_sub(samples);
+        mp3::LameTag::new(0, padding.min(u64::from(u16::MAX)) as u16)
+    }
+
+    /// The number of frames and bytes of the audio as it is served, which is the upstream stream
+    /// without its prefix.
+    fn audio_len(&self, stream: &mp3::StreamPrefix) -> (u64, u64) {
+        let format = &stream.format;
+        if self.inner.exact_size {
+            if let Some(len) = self.stream_length() {
+                let bytes = len.saturating_sub(stream.audio_offset());
+                return (format.frames_for_len(bytes), bytes);
+            }
+        }
+        let frames = format.frames_for_duration(self.track.duration_ms as u64);
+        (frames, format.stream_len(frames))
+    }
+
+    /// The length of the upstream audio stream if it has been seen before.
+    fn stream_length(&self) -> Option<u64> {
+        let value = self.inner.cache.get(&stream_length_key(self.track.id))?;
+        String::from_utf8(value).ok()?.parse().ok()
+    }
+}
+
+fn stream_length_key(track_id: i64) -> String {
+    format!("stream-length/{}", track_id)
+}
+
+fn stream_prefix_key(track_id: i64) -> String {
+    format!("stream-prefix/{}", track_id)
+}
+
+/// What precedes the audio in the stream of a track, if the stream has been seen before.
+fn stream_prefix(inner: &RootState, track_id: i64) -> Option<mp3::StreamPrefix> {
+    let value = inner.cache.get(&stream_prefix_key(track_id))?;
+    mp3::StreamPrefix::decode(&value)
+}
+
+/// Downloads something once and keeps it in the cache. An empty entry records that it is not
+/// available, so that is not asked again either.
+fn cached_download(
+    inner: &RootState,
+    key: &str,
+    fetch: impl FnOnce() -> Result<Vec<u8>, Box<soundcloud::Error>>,
+) -> Option<Vec<u8>> {
+    if let Some(data) = inner.cache.get(key) {
+        return Some(data).filter(|d| !d.is_empty());
+    }
+    match fetch() {
+        Ok(data) => {
+            inner.cache.put(key, &data);
+            Some(data)
+        }
+        Err(err)
+            if matches!(
+                *err,
+                soundcloud::Error::ArtworkNotAvailable | soundcloud::Error::WaveformNotAvailable
+            ) =>
+        {
+            inner.cache.put(key, &[]);
+            None
+        }
+        Err(err) => {
+            error!("{}", err);
+            None
+        }
+    }
+}
+
+/// The track object as it was returned by the API.
+#[derive(Clone)]
+pub struct TrackJson {
+    track: soundcloud::Track,
+}
+
+impl TrackJson {
+    fn contents(&self) -> Vec<u8> {
+        let mut buf = serde_json::to_vec_pretty(&self.track.raw).unwrap();
+        buf.push(b'\n');
+        buf
+    }
+}
+
+impl filesystem::Meta for TrackJson {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.track.last_modified,
+            ctime: self.track.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "track_json",
+            id: self.track.id,
+        })
+    }
+}
+
+impl filesystem::File for TrackJson {
+    type Reader = io::Cursor<Vec<u8>>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        Ok(io::Cursor::new(self.contents()))
+    }
+
+    fn size(&self) -> Result<u64, Self::Error> {
+        Ok(self.contents().len() as u64)
+    }
+}
+
+/// The waveform of a track as normalized samples.
+#[derive(Clone)]
+pub struct Waveform<'a> {
+    inner: &'a RootState,
+    track: soundcloud::Track,
+}
+
+/// The number of samples in waveform files. SoundCloud waveforms have this many, others are
+/// resampled so all files have the same size.
+const WAVEFORM_SAMPLES: usize = 1800;
+
+/// The size of waveform files: the samples are written as "0.000" and separated by commas, in
+/// brackets and followed by a newline.
+const WAVEFORM_JSON_LEN: u64 = WAVEFORM_SAMPLES as u64 * 6 + 2;
+
+impl Waveform<'_> {
+    /// The waveform is downloaded when it is first read.
+    fn contents(&self) -> io::Result<Vec<u8>> {
+        let key = format!("waveform/{}", self.track.id);
+        cached_download(self.inner, &key, || {
+            let waveform = self.track.waveform().map_err(Box::new)?;
+            let samples: Vec<_> = waveform
+                .resampled(WAVEFORM_SAMPLES)
+                .iter()
+                .map(|sample| format!("{:.3}", sample))
+                .collect();
+            Ok(format!("[{}]\n", samples.join(",")).into_bytes())
+        })
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "waveform of track {} is not available",
+                self.track.id
+            ))
+        })
+    }
+}
+
+impl filesystem::Meta for Waveform<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.track.last_modified,
+            ctime: self.track.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "track_waveform",
+            id: self.track.id,
+        })
+    }
+}
+
+impl<'a> filesystem::File for Waveform<'a> {
+    type Reader = Box<dyn ReadSeek + 'a>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        let waveform = self.clone();
+        let data = LazyOpen::with_size_hint(WAVEFORM_JSON_LEN, move || {
+            Ok(io::Cursor::new(waveform.contents()?))
+        });
+        Ok(Box::new(Fit::new(data, WAVEFORM_JSON_LEN, [b' '])))
+    }
+
+    /// The size is known without downloading the waveform.
+    fn size(&self) -> Result<u64, Self::Error> {
+        Ok(WAVEFORM_JSON_LEN)
+    }
+}
+
+fn artwork_key(track_id: i64, size: u32) -> String {
+    format!("artwork/{}/{}", track_id, size)
+}
+
+#[derive(Clone)]
+enum ImageSource {
+    TrackArtwork(Box<soundcloud::Track>),
+    TrackWaveform(Box<soundcloud::Track>),
+    UserAvatar(Box<soundcloud::User>),
+    PlaylistArtwork(Box<soundcloud::Playlist>),
+}
+
+/// An image that is downloaded when it is first needed and then cached.
+#[derive(Clone)]
+pub struct Image<'a> {
+    inner: &'a RootState,
+    source: ImageSource,
+}
+
+impl Image<'_> {
+    fn cache_key(&self) -> String {
+        match &self.source {
+            ImageSource::TrackArtwork(track) => artwork_key(track.id, self.inner.artwork_size),
+            ImageSource::TrackWaveform(track) => format!("waveform-image/{}", track.id),
+            ImageSource::UserAvatar(user) => {
+                format!("avatar/{}/{}", user.id, self.inner.artwork_size)
+            }
+            ImageSource::PlaylistArtwork(playlist) => {
+                format!(
+                    "playlist-artwork/{}/{}",
+                    playlist.id, self.inner.artwork_size
+                )
+            }
+        }
+    }
+
+    fn data(&self) -> Option<Vec<u8>> {
+        let size = self.inner.artwork_size;
+        cached_download(self.inner, &self.cache_key(), || match &self.source {
+            ImageSource::TrackArtwork(track) => {
+                track.artwork(size).map(|(data, _)| data).map_err(Box::new)
+            }
+            ImageSource::TrackWaveform(track) => track
+                .waveform_image()
+                .map(|(data, _)| data)
+                .map_err(Box::new),
+            ImageSource::UserAvatar(user) => {
+                user.avatar(size).map(|(data, _)| data).map_err(Box::new)
+            }
+            ImageSource::PlaylistArtwork(playlist) => playlist
+                .artwork(size)
+                .map(|(data, _)| data)
+                .map_err(Box::new),
+        })
+    }
+}
+
+impl filesystem::Meta for Image<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        let mtime = match &self.source {
+            ImageSource::TrackArtwork(track) | ImageSource::TrackWaveform(track) => {
+                track.last_modified
+            }
+            ImageSource::UserAvatar(user) => user.last_modified,
+            ImageSource::PlaylistArtwork(playlist) => playlist.last_modified,
+        };
+        Ok(filesystem::Metadata {
+            mtime,
+            ctime: mtime,
+            perm: 0o444,
+        })
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(match &self.source {
+            ImageSource::TrackArtwork(track) => filesystem::Identity {
+                kind: "track_artwork",
+                id: track.id,
+            },
+            ImageSource::TrackWaveform(track) => filesystem::Identity {
+                kind: "track_waveform_image",
+                id: track.id,
+            },
+            ImageSource::UserAvatar(user) => filesystem::Identity {
+                kind: "user_avatar",
+                id: user.id,
+            },
+            ImageSource::PlaylistArtwork(playlist) => filesystem::Identity {
+                kind: "playlist_artwork",
+                id: playlist.id,
+            },
+        })
+    }
+}
+
+impl<'a> filesystem::File for Image<'a> {
+    type Reader = Box<dyn ReadSeek + 'a>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        let size = self.size()?;
+        let image = self.clone();
+        let data = LazyOpen::with_size_hint(size, move || {
+            let data = image.data().ok_or_else(|| {
+                io::Error::other(format!("image {} is not available", image.cache_key()))
+            })?;
+            Ok(io::Cursor::new(data))
+        });
+        Ok(Box::new(Fit::new(data, size, [0])))
+    }
+
+    /// The size is taken from the cache, or else asked for with a HEAD request. The image is only
+    /// downloaded to learn its size if the server does not tell.
+    fn size(&self) -> Result<u64, Self::Error> {
+        let key = self.cache_key();
+        if let Some(data) = self.inner.cache.get(&key) {
+            return Ok(data.len() as u64);
+        }
+        let len_key = format!("length/{}", key);
+        if let Some(len) = self.inner.cache.get(&len_key) {
+            if let Some(len) = String::from_utf8(len).ok().and_then(|s| s.parse().ok()) {
+                return Ok(len);
+            }
+        }
+
+        let size = self.inner.artwork_size;
+        let rs = match &self.source {
+            ImageSource::TrackArtwork(track) => track.artwork_len(size),
+            ImageSource::TrackWaveform(track) => track.waveform_image_len(),
+            ImageSource::UserAvatar(user) => user.avatar_len(size),
+            ImageSource::PlaylistArtwork(playlist) => playlist.artwork_len(size),
+        };
+        let len = match rs {
+            Ok(Some(len)) => len,
+            Ok(None) => self.data().map_or(0, |data| data.len() as u64),
+            Err(soundcloud::Error::ArtworkNotAvailable)
+            | Err(soundcloud::Error::WaveformNotAvailable) => 0,
+            Err(err) => return Err(err.into()),
+        };
+        self.inner.cache.put(&len_key, len.to_string().as_bytes());
+        Ok(len)
+    }
+}
+
+/// The comments of a track if they have been fetched during this mount.
+fn fetched_comments(inner: &RootState, track_id: i64) -> Option<Vec<soundcloud::Comment>> {
+    inner.comments.lock().unwrap().get(&track_id).cloned()
+}
+
+/// Fetches the comments of a track, once per mount.
+fn track_comments(
+    inner: &RootState,
+    track: &soundcloud::Track,
+) -> Result<Vec<soundcloud::Comment>, Error> {
+    if let Some(comments) = fetched_comments(inner, track.id) {
+        return Ok(comments);
+    }
+    let comments = track.comments(&inner.sc_client)?;
+    inner
+        .comments
+        .lock()
+        .unwrap()
+        .insert(track.id, comments.clone());
+    Ok(comments)
+}
+
+/// The timed comments of a track as a text file.
+#[derive(Clone)]
+pub struct Comments<'a> {
+    inner: &'a RootState,
+    track: soundcloud::Track,
+}
+
+/// The number of bytes that a comment is assumed to take up before the comments are fetched.
+const COMMENT_LEN_ESTIMATE: u64 = 64;
+
+impl Comments<'_> {
+    fn contents(&self, fetched: &[soundcloud::Comment]) -> Vec<u8> {
+        let format = stream_prefix(self.inner, self.track.id)
+            .map_or(mp3::DEFAULT_FORMAT, |stream| stream.format);
+        let offset_ms = audio_offset_ms(self.inner, &format);
+        let timed = comments::timed(fetched, offset_ms);
+        comments::lrc(&self.track, &timed).into_bytes()
+    }
+}
+
+impl filesystem::Meta for Comments<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.track.last_modified,
+            ctime: self.track.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "track_comments",
+            id: self.track.id,
+        })
+    }
+}
+
+impl filesystem::File for Comments<'_> {
+    type Reader = io::Cursor<Vec<u8>>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        let fetched = track_comments(self.inner, &self.track)?;
+        Ok(io::Cursor::new(self.contents(&fetched)))
+    }
+
+    /// The comments are fetched when the file is first opened. Until then, the size is estimated
+    /// from the number of comments.
+    fn size(&self) -> Result<u64, Self::Error> {
+        if let Some(fetched) = fetched_comments(self.inner, self.track.id) {
+            return Ok(self.contents(&fetched).len() as u64);
+        }
+        let count = self.track.comment_count.unwrap_or(0).max(0) as u64;
+        Ok(self.contents(&[]).len() as u64 + count * COMMENT_LEN_ESTIMATE)
+    }
+}
+
+/// The time that players spend on the zero frames that are inserted before the audio.
+fn audio_offset_ms(inner: &RootState, format: &mp3::FrameHeader) -> u64 {
+    if !inner.mpeg_padding {
+        return 0;
+    }
+    PADDING_START * format.samples_per_frame() * 1000 / u64::from(format.sample_rate)
+}
+
+/// A cue sheet that splits a track into the chapters of its tracklist.
+#[derive(Clone)]
+pub struct CueSheet<'a> {
+    inner: &'a RootState,
+    track: soundcloud::Track,
+    audio_name: String,
+}
+
+impl CueSheet<'_> {
+    fn contents(&self) -> String {
+        let format = stream_prefix(self.inner, self.track.id)
+            .map_or(mp3::DEFAULT_FORMAT, |stream| stream.format);
+        let chapters = chapters::from_track(&self.track, audio_offset_ms(self.inner, &format));
+        chapters::cue_sheet(
+            &self.track,
+            &self.audio_name,
+            &chapters,
+            &self.inner.title_rules,
+        )
+    }
+}
+
+impl filesystem::Meta for CueSheet<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.track.last_modified,
+            ctime: self.track.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "track_cue",
+            id: self.track.id,
+        })
+    }
+}
+
+impl filesystem::File for CueSheet<'_> {
+    type Reader = io::Cursor<Vec<u8>>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        Ok(io::Cursor::new(self.contents().into_bytes()))
+    }
+
+    fn size(&self) -> Result<u64, Self::Error> {
+        Ok(self.contents().len() as u64)
+    }
+}
+
+/// The name of the hidden directory through which a running mount can be controlled.
+const CONTROL_DIR: &str = ".control";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ControlKind {
+    /// Writing anything makes every directory fetch its entries again.
+    Refresh,
+    /// Writing usernames, separated by whitespace, recurses into them like --user does.
+    AddUser,
+    /// Reading reports what the mount has been doing.
+    Stats,
+}
+
+const CONTROL_FILES: &[(&str, ControlKind)] = &[
+    ("refresh", ControlKind::Refresh),
+    ("add-user", ControlKind::AddUser),
+    ("stats", ControlKind::Stats),
+];
+
+#[derive(Clone)]
+pub struct ControlDir<'a> {
+    inner: &'a RootState,
+}
+
+impl filesystem::Meta for ControlDir<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        let now = Utc::now();
+        Ok(filesystem::Metadata {
+            mtime: now,
+            ctime: now,
+            perm: 0o555,
+        })
+    }
+}
+
+impl<'a> filesystem::Directory<Root<'a>> for ControlDir<'a> {
+    fn files(&self) -> Result<Vec<(String, filesystem::Node<Root<'a>>)>, Self::Error> {
+        let files = CONTROL_FILES
+            .iter()
+            .map(|&(name, kind)| {
+                let control = Control {
+                    inner: self.inner,
+                    kind,
+                };
+                (
+                    name.to_string(),
+                    filesystem::Node::File(File::Control(control)),
+                )
+            })
+            .collect();
+        Ok(files)
+    }
+}
+
+#[derive(Clone)]
+pub struct Control<'a> {
+    inner: &'a RootState,
+    kind: ControlKind,
+}
+
+impl Control<'_> {
+    fn contents(&self) -> String {
+        match self.kind {
+            ControlKind::Stats => self.stats(),
+            _ => String::new(),
+        }
+    }
+
+    /// The report has a fixed number of lines of fixed width, so its size does not change with the
+    /// counters. The kernel may hold on to the size it has been told before.
+    fn stats(&self) -> String {
+        let requests = soundcloud::request_stats();
+        let (hits, misses) = self.inner.cache.stats();
+        let fs = filesystem::stats();
+        let rows = [
+            ("requests", requests.requests.to_string()),
+            ("request retries", requests.retries.to_string()),
+            ("request failures", requests.failures.to_string()),
+            ("cache hits", hits.to_string()),
+            ("cache misses", misses.to_string()),
+            ("cache hit rate", hit_rate(hits, misses)),
+            ("directory cache hits", fs.dir_cache_hits.to_string()),
+            ("directory cache misses", fs.dir_cache_misses.to_string()),
+            (
+                "directory cache hit rate",
+                hit_rate(fs.dir_cache_hits, fs.dir_cache_misses),
+            ),
+            ("open files", fs.open_files.to_string()),
+            ("open directories", fs.open_dirs.to_string()),
+        ];
+        rows.iter()
+            .map(|(name, value)| format!("{:<26}{:>14}\n", format!("{}:", name), value))
+            .collect()
+    }
+}
+
+fn hit_rate(hits: u64, misses: u64) -> String {
+    match hits + misses {
+        0 => "-".to_string(),
+        total => format!("{:.1}%", hits as f64 * 100.0 / total as f64),
+    }
+}
+
+impl filesystem::Meta for Control<'_> {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        let now = Utc::now();
+        Ok(filesystem::Metadata {
+            mtime: now,
+            ctime: now,
+            perm: match self.kind {
+                ControlKind::Stats => 0o444,
+                _ => 0o644,
+            },
+        })
+    }
+}
+
+impl filesystem::File for Control<'_> {
+    type Reader = io::Cursor<Vec<u8>>;
+
+    fn open_ro(&self) -> Result<Self::Reader, Self::Error> {
+        Ok(io::Cursor::new(self.contents().into_bytes()))
+    }
+
+    fn size(&self) -> Result<u64, Self::Error> {
+        Ok(self.contents().len() as u64)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(), Self::Error> {
+        match self.kind {
+            ControlKind::Refresh => {
+                info!("refreshing all directories");
+                filesystem::invalidate_all();
+            }
+            ControlKind::AddUser => {
+                for name in String::from_utf8_lossy(data).split_whitespace() {
+                    let user = soundcloud::User::by_name(&self.inner.sc_client, name)?;
+                    show_user(self.inner, &user.permalink)?;
+                }
+            }
+            ControlKind::Stats => return Err(Error::ReadOnly),
+        }
+        Ok(())
+    }
+}
+
+// Track references are only created by linking, so the size difference does not add up.
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+pub enum Link {
+    User(UserReference),
+    Track(TrackReference),
+}
+
+impl filesystem::Meta for Link {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        match self {
+            Link::User(f) => f.metadata(),
+            Link::Track(f) => f.metadata(),
+        }
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        match self {
+            Link::User(f) => f.identity(),
+            Link::Track(f) => f.identity(),
+        }
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        match self {
+            Link::User(f) => f.xattrs(),
+            Link::Track(f) => f.xattrs(),
+        }
+    }
+}
+
+impl filesystem::Symlink for Link {
+    fn read_link(&self) -> Result<PathBuf, Self::Error> {
+        match self {
+            Link::User(f) => f.read_link(),
+            Link::Track(f) => f.read_link(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UserReference {
+    user: soundcloud::User,
+}
+
+impl filesystem::Meta for UserReference {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
             mtime: self.user.last_modified,
             ctime: self.user.last_modified,
             perm: 0o444,
         })
     }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "user_reference",
+            id: self.user.id,
+        })
+    }
+
+    fn xattrs(&self) -> Result<Vec<(String, Vec<u8>)>, Self::Error> {
+        Ok(user_xattrs(&self.user))
+    }
 }
 
 impl filesystem::Symlink for UserReference {
@@ -449,6 +2016,52 @@ impl filesystem::Symlink for UserReference {
     }
 }
 
+/// A link to the audio file of a track in the directory of its user.
+#[derive(Clone)]
+pub struct TrackReference {
+    track: soundcloud::Track,
+    /// The number of directories between the link and the root.
+    depth: usize,
+}
+
+impl filesystem::Meta for TrackReference {
+    type Error = Error;
+    fn metadata(&self) -> Result<filesystem::Metadata, Self::Error> {
+        Ok(filesystem::Metadata {
+            mtime: self.track.last_modified,
+            ctime: self.track.last_modified,
+            perm: 0o444,
+        })
+    }
+
+    fn identity(&self) -> Option<filesystem::Identity> {
+        Some(filesystem::Identity {
+            kind: "track_reference",
+            id: self.track.id,
+        })
+    }
+}
+
+impl filesystem::Symlink for TrackReference {
+    fn read_link(&self) -> Result<PathBuf, Self::Error> {
+        let mut path: PathBuf = iter::repeat_n("..", self.depth).collect();
+        path.push(&self.track.user.permalink);
+        path.push(format!("{}.mp3", self.track.permalink));
+        Ok(path)
+    }
+}
+
+fn xattr(name: &str, value: impl fmt::Display) -> (String, Vec<u8>) {
+    (
+        format!("{}{}", XATTR_PREFIX, name),
+        value.to_string().into_bytes(),
+    )
+}
+
+fn user_xattrs(user: &soundcloud::User) -> Vec<(String, Vec<u8>)> {
+    vec![xattr("user_id", user.id), xattr("url", &user.permalink_url)]
+}
+
 fn is_valid_file(name: impl AsRef<str>) -> bool {
     match name.as_ref() {
         "AACS" | "BACKUP" | "PLAYLIST" | "BDMV" | "bdmv" => false,
@@ -456,3 +2069,404 @@ fn is_valid_file(name: impl AsRef<str>) -> bool {
         _ => true,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filesystem::{Directory, File as _, Meta, Symlink};
+    use crate::soundcloud::{lock_fixtures, test_fixtures, Fixtures};
+    use std::io::Read;
+
+    fn test_state() -> RootState {
+        RootState {
+            sc_client: soundcloud::Client::anonymous().unwrap(),
+            me: None,
+            show: Mutex::new(Vec::new()),
+            state_file: None,
+            mpeg_padding: true,
+            id3_download_images: false,
+            artwork_size: 500,
+            artwork_files: true,
+            json_files: true,
+            waveform_files: WaveformFiles::Json,
+            title_rules: TitleRules::new::<&str>(&[]).unwrap(),
+            chapters: ChapterMode::Cue,
+            id3v1: false,
+            comments: Mutex::new(HashMap::new()),
+            comment_files: true,
+            id3_comments: false,
+            exact_size: false,
+            cache: Cache::new(None),
+        }
+    }
+
+    fn profile(state: &RootState) -> UserProfile<'_> {
+        UserProfile {
+            inner: state,
+            user: soundcloud::User::by_name(&state.sc_client, "wright-and-bastard").unwrap(),
+            recurse: false,
+        }
+    }
+
+    fn names<'a>(dir: &impl Directory<Root<'a>, Error = Error>) -> Vec<String> {
+        dir.files()
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    fn kind(node: filesystem::Node<Root<'_>>) -> &'static str {
+        match node {
+            filesystem::Node::File(file) => file.identity().unwrap().kind,
+            _ => panic!("not a file"),
+        }
+    }
+
+    #[test]
+    fn track_file_lookup() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let mut state = test_state();
+        let lookups = [
+            ("the-fat-dandy-butterfly-slims", "track"),
+            ("the-fat-dandy-butterfly-slims.mp3", "track"),
+            ("the-fat-dandy-butterfly-slims.cue", "track_cue"),
+            ("the-fat-dandy-butterfly-slims.jpg", "track_artwork"),
+            ("the-fat-dandy-butterfly-slims.json", "track_json"),
+            (
+                "the-fat-dandy-butterfly-slims.waveform.json",
+                "track_waveform",
+            ),
+            (
+                "the-fat-dandy-butterfly-slims.comments.txt",
+                "track_comments",
+            ),
+        ];
+        for &(name, expected) in &lookups {
+            let node = profile(&state).file_by_name(name).unwrap();
+            assert_eq!(expected, kind(node), "{}", name);
+        }
+
+        state.waveform_files = WaveformFiles::Png;
+        let name = "the-fat-dandy-butterfly-slims.waveform.png";
+        let node = profile(&state).file_by_name(name).unwrap();
+        assert_eq!("track_waveform_image", kind(node));
+        let name = "the-fat-dandy-butterfly-slims.waveform.json";
+        assert!(matches!(
+            profile(&state).file_by_name(name),
+            Err(Error::ChildNotFound)
+        ));
+    }
+
+    #[test]
+    fn waveform_size() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let state = test_state();
+        let name = "the-fat-dandy-butterfly-slims.waveform.json";
+        let waveform = match profile(&state).file_by_name(name).unwrap() {
+            filesystem::Node::File(file) => file,
+            _ => panic!("not a file"),
+        };
+        assert_eq!(WAVEFORM_JSON_LEN, waveform.size().unwrap());
+
+        // The recorded waveform has half as many samples, which are stretched to fit.
+        let mut buf = Vec::new();
+        waveform.open_ro().unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(WAVEFORM_JSON_LEN, buf.len() as u64);
+        let samples: Vec<f32> = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(WAVEFORM_SAMPLES, samples.len());
+        assert_eq!(&[0.0, 0.0, 0.007, 0.007], &samples[..4]);
+
+        // A waveform that can not be downloaded fails the read rather than reading as blanks.
+        let mut track = soundcloud::Track::by_permalink(
+            &state.sc_client,
+            "wright-and-bastard",
+            "the-fat-dandy-butterfly-slims",
+        )
+        .unwrap();
+        track.id = 1;
+        track.waveform_url = Some("https://w1.sndcdn.com/missing_m.png".to_string());
+        let missing = Waveform {
+            inner: &state,
+            track,
+        };
+        assert_eq!(WAVEFORM_JSON_LEN, missing.size().unwrap());
+        buf.clear();
+        assert!(missing.open_ro().unwrap().read_to_end(&mut buf).is_err());
+    }
+
+    #[test]
+    fn comments_fetched_on_open() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let mut state = test_state();
+        state.id3_comments = true;
+        let file = |name| match profile(&state).file_by_name(name).unwrap() {
+            filesystem::Node::File(file) => file,
+            _ => panic!("not a file"),
+        };
+        let audio = file("the-fat-dandy-butterfly-slims.mp3");
+        let comments = file("the-fat-dandy-butterfly-slims.comments.txt");
+
+        // Learning the size does not fetch the comments.
+        let audio_size = audio.size().unwrap();
+        let estimate = comments.size().unwrap();
+        assert!(state.comments.lock().unwrap().is_empty());
+
+        let mut buf = String::new();
+        comments
+            .open_ro()
+            .unwrap()
+            .read_to_string(&mut buf)
+            .unwrap();
+        assert!(buf.ends_with("] Bob: what a drop\n"), "{}", buf);
+        assert_ne!(estimate, comments.size().unwrap());
+        assert_eq!(buf.len() as u64, comments.size().unwrap());
+        // The audio file now has room for the comments in its tag.
+        assert!(audio.size().unwrap() > audio_size);
+    }
+
+    #[test]
+    fn user_directory() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let state = test_state();
+        let mut user = profile(&state);
+        user.recurse = true;
+        let expected = vec![
+            "cover.jpg",
+            "favorites",
+            "following",
+            "playlists",
+            "the-fat-dandy-butterfly-slims.cue",
+            "the-fat-dandy-butterfly-slims.jpg",
+            "the-fat-dandy-butterfly-slims.json",
+            "the-fat-dandy-butterfly-slims.waveform.json",
+            "the-fat-dandy-butterfly-slims.comments.txt",
+            "the-fat-dandy-butterfly-slims.mp3",
+        ];
+        assert_eq!(expected, names(&user));
+        assert_eq!("user_avatar", kind(user.file_by_name("cover.jpg").unwrap()));
+
+        // An image that can not be downloaded fails the read rather than reading as zeros.
+        state.cache.put("length/avatar/14559536/500", b"1234");
+        let cover = match user.file_by_name("cover.jpg").unwrap() {
+            filesystem::Node::File(file) => file,
+            _ => panic!("not a file"),
+        };
+        assert_eq!(1234, cover.size().unwrap());
+        let mut buf = Vec::new();
+        assert!(cover.open_ro().unwrap().read_to_end(&mut buf).is_err());
+
+        // The JSON file holds the track object as it was returned by the API.
+        let json = match user
+            .file_by_name("the-fat-dandy-butterfly-slims.json")
+            .unwrap()
+        {
+            filesystem::Node::File(file) => file,
+            _ => panic!("not a file"),
+        };
+        let mut buf = Vec::new();
+        json.open_ro().unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len() as u64, json.size().unwrap());
+        let raw: serde_json::Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(609233313, raw["id"]);
+        assert_eq!("NOT_APPLICABLE", raw["monetization_model"]);
+    }
+
+    #[test]
+    fn like_and_unlike() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let mut state = test_state();
+        let target = Path::new("../../wright-and-bastard/the-fat-dandy-butterfly-slims.mp3");
+        let user = profile(&state).user;
+        let favorites = UserFavorites {
+            inner: &state,
+            user: user.clone(),
+        };
+        assert!(matches!(
+            favorites.symlink("a.mp3", target),
+            Err(Error::ReadOnly)
+        ));
+
+        state.me = Some(user.clone());
+        let favorites = UserFavorites {
+            inner: &state,
+            user,
+        };
+        match favorites.symlink("a.mp3", target).unwrap() {
+            filesystem::Node::Symlink(link) => assert_eq!(target, link.read_link().unwrap()),
+            _ => panic!("not a symlink"),
+        }
+        assert!(matches!(
+            favorites.symlink("a.mp3", Path::new("../../wright-and-bastard")),
+            Err(Error::BadLinkTarget(_))
+        ));
+
+        let name = "wright-and-bastard_-_the-fat-dandy-butterfly-slims.mp3";
+        assert!(names(&favorites).iter().any(|n| n == name));
+        favorites.unlink(name).unwrap();
+        assert!(matches!(
+            favorites.unlink("wright-and-bastard_-_the-fat-dandy-butterfly-slims.json"),
+            Err(Error::ReadOnly)
+        ));
+    }
+
+    #[test]
+    fn follow_and_unfollow() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let mut state = test_state();
+        let user = profile(&state).user;
+        state.me = Some(user.clone());
+        let following = UserFollowing {
+            inner: &state,
+            user,
+        };
+        let target = Path::new("../../other-user");
+        match following.symlink("other-user", target).unwrap() {
+            filesystem::Node::Symlink(link) => assert_eq!(target, link.read_link().unwrap()),
+            _ => panic!("not a symlink"),
+        }
+        assert!(matches!(
+            following.symlink("x", Path::new("../../other-user/cover.jpg")),
+            Err(Error::BadLinkTarget(_))
+        ));
+
+        assert_eq!(vec!["other-user"], names(&following));
+        following.unlink("other-user").unwrap();
+    }
+
+    #[test]
+    fn control_files() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let state = test_state();
+        let control = ControlDir { inner: &state };
+        assert_eq!(vec!["refresh", "add-user", "stats"], names(&control));
+
+        let file = |name| match control.file_by_name(name).unwrap() {
+            filesystem::Node::File(file) => file,
+            _ => panic!("not a file"),
+        };
+        let requests = soundcloud::request_stats().requests;
+        file("add-user").write(b"other-user\n").unwrap();
+        assert!(is_shown(&state, "other-user"));
+        assert!(matches!(file("stats").write(b"x"), Err(Error::ReadOnly)));
+        // Replayed requests are counted like real ones.
+        assert!(soundcloud::request_stats().requests > requests);
+
+        let stats = file("stats");
+        let mut buf = Vec::new();
+        stats.open_ro().unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len() as u64, stats.size().unwrap());
+        let report = String::from_utf8(buf).unwrap();
+        assert!(report.starts_with("requests:"));
+        assert!(!report.lines().next().unwrap().ends_with(" 0"));
+    }
+
+    #[test]
+    fn add_and_remove_users() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let state = test_state();
+        let root = Root::new(&state);
+        let cache_root = filesystem::CacheRoot::new(&root);
+        let mut fs = filesystem::FS::new(&cache_root, 0, 0);
+        let root_ino = filesystem::INO_ROOT;
+
+        // Users that are not recursed into are not found, so the kernel goes on to create them.
+        let lookup = |fs: &mut filesystem::FS<_>| fs.lookup_child(root_ino, "other-user");
+        assert_eq!(Some(libc::ENOENT), lookup(&mut fs).err());
+        assert_eq!(Some(libc::EROFS), fs.make_dir(root_ino, "me").err());
+        assert_eq!(
+            Some(libc::ENOENT),
+            fs.make_dir(root_ino, "no-such-user").err()
+        );
+        let ino = fs.make_dir(root_ino, "other-user").unwrap().ino;
+        assert_eq!(ino, lookup(&mut fs).unwrap().ino);
+        assert!(is_shown(&state, "other-user"));
+
+        fs.remove_dir(root_ino, "other-user").unwrap();
+        assert_eq!(Some(libc::ENOENT), lookup(&mut fs).err());
+        assert_eq!(
+            Some(libc::ENOENT),
+            fs.remove_dir(root_ino, "other-user").err()
+        );
+    }
+
+    #[test]
+    fn edit_sets() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let mut state = test_state();
+        state.me = Some(profile(&state).user);
+        let playlists = UserPlaylists {
+            inner: &state,
+            user: state.me.clone().unwrap(),
+        };
+        assert_eq!(vec!["drommar", "leeg"], names(&playlists));
+        let set = match playlists.file_by_name("drommar").unwrap() {
+            filesystem::Node::Directory(Dir::Playlist(set)) => set,
+            _ => panic!("not a set"),
+        };
+        let files = names(&set);
+        assert_eq!("cover.jpg", files[0]);
+        let audio = "wright-and-bastard_-_the-fat-dandy-butterfly-slims.mp3";
+        assert!(files.iter().any(|n| n == audio));
+
+        // Tracks can be linked from other sets as well as from the directory of their user.
+        let targets = [
+            "../drommar/wright-and-bastard_-_the-fat-dandy-butterfly-slims.mp3",
+            "../../favorites/wright-and-bastard_-_the-fat-dandy-butterfly-slims.mp3",
+            "../../../wright-and-bastard/the-fat-dandy-butterfly-slims.mp3",
+        ];
+        for target in &targets {
+            match set.symlink("a.mp3", Path::new(target)).unwrap() {
+                filesystem::Node::Symlink(link) => assert_eq!(
+                    Path::new("../../../wright-and-bastard/the-fat-dandy-butterfly-slims.mp3"),
+                    link.read_link().unwrap()
+                ),
+                _ => panic!("not a symlink"),
+            }
+        }
+        set.unlink(audio).unwrap();
+
+        assert!(matches!(playlists.rmdir("drommar"), Err(Error::NotEmpty)));
+        // Only the artwork of this set is left, which does not count as content.
+        match playlists.file_by_name("leeg").unwrap() {
+            filesystem::Node::Directory(dir) => assert_eq!(vec!["cover.jpg"], names(&dir)),
+            _ => panic!("not a set"),
+        }
+        playlists.rmdir("leeg").unwrap();
+        assert!(matches!(playlists.mkdir("New Set"), Err(Error::BadName(_))));
+        match playlists.mkdir("new-set").unwrap() {
+            filesystem::Node::Directory(Dir::Playlist(set)) => {
+                assert_eq!("new-set", set.playlist.permalink)
+            }
+            _ => panic!("not a set"),
+        }
+    }
+
+    #[test]
+    fn audio_size_fixed_while_open() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let state = test_state();
+        let audio = match profile(&state)
+            .file_by_name("the-fat-dandy-butterfly-slims.mp3")
+            .unwrap()
+        {
+            filesystem::Node::File(file) => file,
+            _ => panic!("not a file"),
+        };
+        let announced = audio.size().unwrap();
+        let mut reader = audio.open_ro().unwrap();
+        // The stream has a higher bitrate than the default, which is learned by opening it.
+        let probed = audio.size().unwrap();
+        assert!(probed > announced);
+
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(announced, buf.len() as u64);
+        assert_eq!(announced, reader.seek(io::SeekFrom::End(0)).unwrap());
+
+        // Files that are opened afterwards have the new size.
+        buf.clear();
+        audio.open_ro().unwrap().read_to_end(&mut buf).unwrap();
+        assert_eq!(probed, buf.len() as u64);
+    }
+}
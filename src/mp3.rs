@@ -1,68 +1,481 @@
 use crate::ioutil;
-use lazy_static::lazy_static;
+use std::convert::TryInto;
 use std::io;
 
 const FRAMES_FLAG: u32 = 0x0000_0001;
 const BYTES_FLAG: u32 = 0x0000_0002;
-//const TOC_FLAG: u32 = 0x0000_0004;
-//const VBR_SCALE_FLAG: u32 = 0x0000_0008;
+const TOC_FLAG: u32 = 0x0000_0004;
+const VBR_SCALE_FLAG: u32 = 0x0000_0008;
 
-const MEAN_FRAME_SIZE: u64 = 417;
+/// The format that is assumed for streams of which no frame has been seen yet: MPEG-1 Layer III,
+/// 128 kbit/s, 44.1 kHz, joint stereo.
+pub const DEFAULT_FORMAT: FrameHeader = FrameHeader {
+    version: Version::Mpeg1,
+    layer: Layer::Layer3,
+    bitrate: 128_000,
+    sample_rate: 44_100,
+    padding: false,
+    channel_mode: ChannelMode::JointStereo,
+    bytes: [0xff, 0xfb, 0x90, 0x64],
+};
 
-lazy_static! {
-    pub static ref ZERO_FRAME: [u8; MEAN_FRAME_SIZE as usize] = {
-        let mut buf = [0; MEAN_FRAME_SIZE as usize];
-        buf[0x00..0x04].copy_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+/// The length of an Info tag with all optional fields present.
+const INFO_TAG_LEN: usize = 0x78;
+const LAME_TAG_LEN: usize = 36;
+const LAME_ENCODER_LEN: usize = 9;
+/// The VBRI tag, written by the Fraunhofer encoder, is always at this offset.
+const VBRI_OFFSET: usize = 4 + 32;
+
+const ID3V2_HEADER_LEN: usize = 10;
+const ID3V2_FOOTER_FLAG: u8 = 0x10;
+
+const BITRATES_V1_L1: [u32; 15] = [
+    0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448,
+];
+const BITRATES_V1_L2: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384,
+];
+const BITRATES_V1_L3: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const BITRATES_V2_L1: [u32; 15] = [
+    0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256,
+];
+const BITRATES_V2_L23: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Version {
+    Mpeg1,
+    Mpeg2,
+    Mpeg25,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Layer {
+    Layer1,
+    Layer2,
+    Layer3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelMode {
+    Stereo,
+    JointStereo,
+    DualChannel,
+    Mono,
+}
+
+/// The 4 byte header that precedes every MPEG audio frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: Version,
+    pub layer: Layer,
+    /// The bitrate in bits per second.
+    pub bitrate: u32,
+    /// The sample rate in Hz.
+    pub sample_rate: u32,
+    pub padding: bool,
+    pub channel_mode: ChannelMode,
+    bytes: [u8; 4],
+}
+
+impl FrameHeader {
+    /// Parses a frame header. None is returned if the bytes do not form a valid header. Free
+    /// format streams are not supported.
+    pub fn parse(b: [u8; 4]) -> Option<FrameHeader> {
+        if b[0] != 0xff || b[1] & 0xe0 != 0xe0 {
+            return None;
+        }
+        let version = match (b[1] >> 3) & 0b11 {
+            0b00 => Version::Mpeg25,
+            0b10 => Version::Mpeg2,
+            0b11 => Version::Mpeg1,
+            _ => return None,
+        };
+        let layer = match (b[1] >> 1) & 0b11 {
+            0b01 => Layer::Layer3,
+            0b10 => Layer::Layer2,
+            0b11 => Layer::Layer1,
+            _ => return None,
+        };
+        let bitrate_index = (b[2] >> 4) as usize;
+        let bitrates = match (version, layer) {
+            (Version::Mpeg1, Layer::Layer1) => &BITRATES_V1_L1,
+            (Version::Mpeg1, Layer::Layer2) => &BITRATES_V1_L2,
+            (Version::Mpeg1, Layer::Layer3) => &BITRATES_V1_L3,
+            (_, Layer::Layer1) => &BITRATES_V2_L1,
+            (_, _) => &BITRATES_V2_L23,
+        };
+        let bitrate = match bitrates.get(bitrate_index) {
+            Some(0) | None => return None,
+            Some(v) => v * 1000,
+        };
+        let sample_rate = match ((b[2] >> 2) & 0b11, version) {
+            (0b11, _) => return None,
+            (i, Version::Mpeg1) => [44_100, 48_000, 32_000][i as usize],
+            (i, Version::Mpeg2) => [22_050, 24_000, 16_000][i as usize],
+            (i, Version::Mpeg25) => [11_025, 12_000, 8_000][i as usize],
+        };
+        let channel_mode = match b[3] >> 6 {
+            0b00 => ChannelMode::Stereo,
+            0b01 => ChannelMode::JointStereo,
+            0b10 => ChannelMode::DualChannel,
+            _ => ChannelMode::Mono,
+        };
+        Some(FrameHeader {
+            version,
+            layer,
+            bitrate,
+            sample_rate,
+            padding: b[2] & 0b10 != 0,
+            channel_mode,
+            bytes: b,
+        })
+    }
+
+    /// Returns the header as it is used for generated frames: without padding and without a CRC.
+    pub fn to_bytes(self) -> [u8; 4] {
+        let mut b = self.bytes;
+        b[1] |= 0b1;
+        b[2] &= !0b10;
+        b
+    }
+
+    pub fn samples_per_frame(&self) -> u64 {
+        match (self.layer, self.version) {
+            (Layer::Layer1, _) => 384,
+            (Layer::Layer2, _) | (Layer::Layer3, Version::Mpeg1) => 1152,
+            (Layer::Layer3, _) => 576,
+        }
+    }
+
+    /// The length of the frame in bytes, including the header.
+    pub fn frame_len(&self) -> u64 {
+        let (slot, padding) = match self.layer {
+            Layer::Layer1 => (4, 4),
+            _ => (1, 1),
+        };
+        let slots = self.samples_per_frame() / 8 / slot * u64::from(self.bitrate)
+            / u64::from(self.sample_rate);
+        slots * slot + if self.padding { padding } else { 0 }
+    }
+
+    /// The number of frames needed to hold audio of the specified duration.
+    pub fn frames_for_duration(&self, duration_ms: u64) -> u64 {
+        let samples = duration_ms * u64::from(self.sample_rate) / 1000;
+        samples.div_ceil(self.samples_per_frame())
+    }
+
+    /// The number of frames in a constant bitrate stream of the specified size.
+    pub fn frames_for_len(&self, len: u64) -> u64 {
+        (len * 8 * u64::from(self.sample_rate))
+            .div_ceil(self.samples_per_frame() * u64::from(self.bitrate))
+    }
+
+    /// The size of a constant bitrate stream of the specified number of frames. Padded frames are
+    /// taken into account, so this is the average frame length times the number of frames.
+    pub fn stream_len(&self, frames: u64) -> u64 {
+        frames * self.samples_per_frame() * u64::from(self.bitrate)
+            / 8
+            / u64::from(self.sample_rate)
+    }
+
+    /// The offset of a Xing or Info tag from the start of the frame.
+    fn info_offset(&self) -> usize {
+        let side_info = match (self.version, self.channel_mode) {
+            (Version::Mpeg1, ChannelMode::Mono) => 17,
+            (Version::Mpeg1, _) => 32,
+            (_, ChannelMode::Mono) => 9,
+            (_, _) => 17,
+        };
+        4 + side_info
+    }
+
+    /// Returns an unpadded header of the same format. If its frames are smaller than the specified
+    /// number of bytes, the lowest bitrate at which they are not is used instead, or the highest
+    /// bitrate if there is none.
+    fn with_min_len(&self, len: u64) -> FrameHeader {
+        let mut header = FrameHeader {
+            padding: false,
+            ..*self
+        };
+        if header.frame_len() >= len {
+            return header;
+        }
+        for index in 1..15 {
+            let mut b = header.bytes;
+            // The upper nibble is the bitrate index, the padding bit is cleared.
+            b[2] = (b[2] & 0x0d) | (index << 4);
+            header = FrameHeader::parse(b).unwrap();
+            if header.frame_len() >= len {
+                break;
+            }
+        }
+        header
+    }
+}
+
+/// Reads the header of the frame at the start of a stream.
+pub fn read_frame_header(mut r: impl io::Read) -> io::Result<FrameHeader> {
+    let mut b = [0; 4];
+    r.read_exact(&mut b)?;
+    FrameHeader::parse(b).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("not an MPEG frame header: {:02x?}", b),
+        )
+    })
+}
+
+/// Describes what precedes the audio frames of an MPEG stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StreamPrefix {
+    /// The format of the first frame.
+    pub format: FrameHeader,
+    /// The length of the ID3v2 tag at the start of the stream, 0 if there is none.
+    pub id3_len: u64,
+    /// The length of the Xing, Info or VBRI frame after the tag, 0 if there is none.
+    pub info_frame_len: u64,
+    /// The LAME tag in the Info frame, if any.
+    pub lame: Option<LameTag>,
+}
+
+impl StreamPrefix {
+    /// The offset of the first audio frame.
+    pub fn audio_offset(&self) -> u64 {
+        self.id3_len + self.info_frame_len
+    }
+
+    /// Encodes the prefix so it can be kept for later, see `decode`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.format.bytes.to_vec();
+        buf.extend_from_slice(&self.id3_len.to_be_bytes());
+        buf.extend_from_slice(&self.info_frame_len.to_be_bytes());
+        if let Some(lame) = &self.lame {
+            buf.extend_from_slice(&lame.0);
+        }
         buf
+    }
+
+    /// Decodes a prefix that was encoded by `encode`. None is returned if the bytes are not a
+    /// valid encoding.
+    pub fn decode(b: &[u8]) -> Option<StreamPrefix> {
+        let lame = match b.get(20..)? {
+            [] => None,
+            tag => Some(LameTag(tag.try_into().ok()?)),
+        };
+        Some(StreamPrefix {
+            format: FrameHeader::parse(b[..4].try_into().ok()?)?,
+            id3_len: u64::from_be_bytes(b[4..12].try_into().ok()?),
+            info_frame_len: u64::from_be_bytes(b[12..20].try_into().ok()?),
+            lame,
+        })
+    }
+}
+
+/// Inspects the start of a stream. The reader is left somewhere after the prefix: at the start
+/// of the second frame if the first frame is audio.
+pub fn read_stream_prefix(mut r: impl io::Read) -> io::Result<StreamPrefix> {
+    let mut head = [0; ID3V2_HEADER_LEN];
+    r.read_exact(&mut head)?;
+
+    let mut id3_len = 0;
+    if head.starts_with(b"ID3") {
+        // The size is a 28 bit "syncsafe" integer and excludes the header and footer.
+        let size = head[6..10]
+            .iter()
+            .fold(0, |acc, &b| (acc << 7) | u64::from(b & 0x7f));
+        let footer = if head[5] & ID3V2_FOOTER_FLAG != 0 {
+            ID3V2_HEADER_LEN as u64
+        } else {
+            0
+        };
+        id3_len = ID3V2_HEADER_LEN as u64 + size + footer;
+        io::copy(
+            &mut io::Read::take(&mut r, id3_len - head.len() as u64),
+            &mut io::sink(),
+        )?;
+        r.read_exact(&mut head)?;
+    }
+
+    let format = read_frame_header(&head[..4])?;
+    let mut frame = head.to_vec();
+    frame.resize((format.frame_len() as usize).max(head.len()), 0);
+    r.read_exact(&mut frame[head.len()..])?;
+
+    let has_tag = |offset: usize, id: &[u8]| frame.get(offset..).is_some_and(|t| t.starts_with(id));
+    let is_info_frame = has_tag(format.info_offset(), b"Xing")
+        || has_tag(format.info_offset(), b"Info")
+        || has_tag(VBRI_OFFSET, b"VBRI");
+    Ok(StreamPrefix {
+        format,
+        id3_len,
+        info_frame_len: if is_info_frame { format.frame_len() } else { 0 },
+        lame: read_lame_tag(&frame),
+    })
+}
+
+/// Returns a single frame of silence in the specified format.
+pub fn zero_frame(format: &FrameHeader) -> Vec<u8> {
+    let format = FrameHeader {
+        padding: false,
+        ..*format
     };
+    let mut buf = vec![0; format.frame_len() as usize];
+    buf[0x00..0x04].copy_from_slice(&format.to_bytes());
+    buf
 }
 
-pub fn zero_frames(count: u64) -> impl io::Read + io::Seek {
-    ioutil::Pattern::new(&ZERO_FRAME[..], ZERO_FRAME.len() as u64 * count)
+pub fn zero_frames(format: &FrameHeader, count: u64) -> impl io::Read + io::Seek {
+    let frame = zero_frame(format);
+    let size = frame.len() as u64 * count;
+    ioutil::Pattern::new(frame, size)
 }
 
-pub fn cbr_header(bytes: u64) -> Vec<u8> {
-    let mut buf = vec![0; 417];
+/// The LAME extension to the Info tag. Among other things, it holds the number of samples that
+/// gapless players should drop at the start and end of the stream.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LameTag([u8; LAME_TAG_LEN]);
 
-    // The MPEG header.
-    buf[0x00..0x04].copy_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+impl LameTag {
+    /// Creates a tag that only holds the gapless playback information.
+    pub fn new(delay: u16, padding: u16) -> Self {
+        let mut tag = LameTag([0; LAME_TAG_LEN]);
+        let encoder = concat!("scfs", env!("CARGO_PKG_VERSION"));
+        copy_from_var_str(&mut tag.0[..LAME_ENCODER_LEN], encoder);
+        tag.set_gapless(delay, padding);
+        tag
+    }
 
-    // "Info" to indicate that this is a header for a CBR stream.
-    buf[0x24..0x28].copy_from_slice(b"Info");
+    /// The encoder version string, e.g. "LAME3.99r".
+    pub fn encoder(&self) -> &[u8] {
+        let encoder = &self.0[..LAME_ENCODER_LEN];
+        let len = encoder
+            .iter()
+            .position(|&b| b == 0)
+            .unwrap_or(encoder.len());
+        &encoder[..len]
+    }
 
-    // Header flags.
-    let flags = FRAMES_FLAG | BYTES_FLAG;
-    buf[0x28..0x2c].copy_from_slice(&flags.to_be_bytes());
+    /// The number of samples added by the encoder at the start of the stream.
+    pub fn delay(&self) -> u16 {
+        (u16::from(self.0[21]) << 4) | (u16::from(self.0[22]) >> 4)
+    }
 
-    // 0x34..0x98: Table of contents used for seeking. Not relevant for CBR.
+    /// The number of samples added by the encoder at the end of the stream.
+    pub fn padding(&self) -> u16 {
+        (u16::from(self.0[22] & 0x0f) << 8) | u16::from(self.0[23])
+    }
 
-    // The number of frames in the file.
-    if flags & FRAMES_FLAG != 0 {
-        let frames = bytes / MEAN_FRAME_SIZE;
-        assert!(frames <= u64::from(std::u32::MAX));
-        buf[0x2c..0x30].copy_from_slice(&(frames as u32).to_be_bytes());
+    /// Sets the delay and padding. Both are 12 bit values, larger values are clamped.
+    pub fn set_gapless(&mut self, delay: u16, padding: u16) {
+        let (delay, padding) = (delay.min(0xfff), padding.min(0xfff));
+        self.0[21] = (delay >> 4) as u8;
+        self.0[22] = ((delay & 0x0f) << 4) as u8 | (padding >> 8) as u8;
+        self.0[23] = padding as u8;
     }
+}
 
-    // The filesize in bytes.
-    if flags & BYTES_FLAG != 0 {
-        assert!(bytes <= u64::from(std::u32::MAX));
-        buf[0x30..0x34].copy_from_slice(&(bytes as u32).to_be_bytes());
+/// Reads the LAME tag from a frame that holds a Xing or Info tag.
+pub fn read_lame_tag(frame: &[u8]) -> Option<LameTag> {
+    let header = FrameHeader::parse([
+        *frame.first()?,
+        *frame.get(1)?,
+        *frame.get(2)?,
+        *frame.get(3)?,
+    ])?;
+    let tag = frame.get(header.info_offset()..)?;
+    if !tag.starts_with(b"Xing") && !tag.starts_with(b"Info") {
+        return None;
+    }
+    let flags = u32::from_be_bytes([*tag.get(4)?, *tag.get(5)?, *tag.get(6)?, *tag.get(7)?]);
+    // The fields in the Xing tag are only present if their flag is set.
+    let lame_offset = 8
+        + if flags & FRAMES_FLAG != 0 { 4 } else { 0 }
+        + if flags & BYTES_FLAG != 0 { 4 } else { 0 }
+        + if flags & TOC_FLAG != 0 { 100 } else { 0 }
+        + if flags & VBR_SCALE_FLAG != 0 { 4 } else { 0 };
+    let lame = tag.get(lame_offset..lame_offset + LAME_TAG_LEN)?;
+    // Other encoders use the same layout, but all put some alphanumeric name here.
+    if !lame[..4].iter().all(u8::is_ascii_alphanumeric) {
+        return None;
     }
+    let mut buf = [0; LAME_TAG_LEN];
+    buf.copy_from_slice(lame);
+    Some(LameTag(buf))
+}
+
+/// Builds the frame holding the Info tag that describes a CBR stream of the specified number of
+/// audio frames and bytes. The tag frame itself is not included in these numbers.
+pub fn cbr_header(format: &FrameHeader, frames: u64, bytes: u64, lame: &LameTag) -> Vec<u8> {
+    let info_offset = format.info_offset();
+    let lame_offset = info_offset + INFO_TAG_LEN;
+    let min_len = lame_offset + LAME_TAG_LEN;
+    let header = format.with_min_len(min_len as u64);
+    let mut buf = zero_frame(&header);
+    buf.resize(buf.len().max(min_len), 0);
+    let total_bytes = bytes + header.frame_len();
+    let tag = &mut buf[info_offset..];
 
-    // 0x34..0x38: VBR scale, whatever that is.
+    // "Info" to indicate that this is a header for a CBR stream.
+    tag[0x00..0x04].copy_from_slice(b"Info");
 
-    // There are also the enc_delay and enc_padding fields, we'll leave them 0.
+    // Header flags. All fields are written, so the LAME tag is at a fixed offset.
+    let flags = FRAMES_FLAG | BYTES_FLAG | TOC_FLAG | VBR_SCALE_FLAG;
+    tag[0x04..0x08].copy_from_slice(&flags.to_be_bytes());
 
-    // The encoder version string. Usually, this is something like "LAME3.99".
-    let encoder = concat!(env!("CARGO_PKG_NAME"), " v", env!("CARGO_PKG_VERSION"));
-    copy_from_var_str(&mut buf[0x9c..0xb0], &encoder);
+    // The number of audio frames in the file.
+    assert!(frames <= u64::from(std::u32::MAX));
+    tag[0x08..0x0c].copy_from_slice(&(frames as u32).to_be_bytes());
 
+    // The size of the stream in bytes, including this frame.
+    assert!(total_bytes <= u64::from(std::u32::MAX));
+    tag[0x0c..0x10].copy_from_slice(&(total_bytes as u32).to_be_bytes());
+
+    // 0x10..0x74: Table of contents used for seeking. Entry i is the position in the stream at i
+    // percent of the duration, scaled to 0..256. The bitrate is constant, so this is linear.
+    for (i, entry) in tag[0x10..0x74].iter_mut().enumerate() {
+        *entry = ((i * 256 + 50) / 100) as u8;
+    }
+
+    // 0x74..0x78: VBR scale, the quality indicator of the encoder. Left 0.
+
+    // The LAME tag. The music length covers the whole stream and the music CRC is left 0 as the
+    // audio is not known up front.
+    let lame_tag = &mut tag[INFO_TAG_LEN..INFO_TAG_LEN + LAME_TAG_LEN];
+    lame_tag.copy_from_slice(&lame.0);
+    lame_tag[28..32].copy_from_slice(&(total_bytes as u32).to_be_bytes());
+    lame_tag[32..34].copy_from_slice(&[0, 0]);
+
+    // The last field is a checksum over the frame up to the checksum itself.
+    let crc_offset = lame_offset + LAME_TAG_LEN - 2;
+    let crc = crc16(&buf[..crc_offset]);
+    buf[crc_offset..crc_offset + 2].copy_from_slice(&crc.to_be_bytes());
+
+    buf.truncate(header.frame_len() as usize);
     buf
 }
 
+/// The CRC-16 variant used by LAME (polynomial 0x8005, reflected, initial value 0).
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &b in data {
+        crc ^= u16::from(b);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
 fn copy_from_var_str(buf: &mut [u8], s: &str) {
     let b = s.as_bytes();
-    buf[..b.len()].copy_from_slice(b);
+    let len = b.len().min(buf.len());
+    buf[..len].copy_from_slice(&b[..len]);
 }
 
 // 00000000: fffb 9064 0000 0000 0000 0000 0000 0000  ...d............
@@ -92,3 +505,243 @@ fn copy_from_var_str(buf: &mut [u8], s: &str) {
 // 00000180: 0000 0000 0000 0000 0000 0000 0000 0000  ................
 // 00000190: 0000 0000 0000 0000 0000 0000 0000 0000  ................
 // 000001a0: 00                                       .
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_default() {
+        assert_eq!(
+            Some(DEFAULT_FORMAT),
+            FrameHeader::parse(DEFAULT_FORMAT.bytes)
+        );
+        assert_eq!(417, DEFAULT_FORMAT.frame_len());
+        assert_eq!(1152, DEFAULT_FORMAT.samples_per_frame());
+    }
+
+    #[test]
+    fn parse_formats() {
+        // MPEG-1 Layer III, 320 kbit/s, 48 kHz, padded, mono.
+        let h = FrameHeader::parse([0xff, 0xfb, 0xe6, 0xc0]).unwrap();
+        assert_eq!(Version::Mpeg1, h.version);
+        assert_eq!(Layer::Layer3, h.layer);
+        assert_eq!(320_000, h.bitrate);
+        assert_eq!(48_000, h.sample_rate);
+        assert_eq!(ChannelMode::Mono, h.channel_mode);
+        assert_eq!(961, h.frame_len());
+
+        // MPEG-2 Layer III, 64 kbit/s, 22.05 kHz.
+        let h = FrameHeader::parse([0xff, 0xf3, 0x80, 0x44]).unwrap();
+        assert_eq!(Version::Mpeg2, h.version);
+        assert_eq!(64_000, h.bitrate);
+        assert_eq!(22_050, h.sample_rate);
+        assert_eq!(576, h.samples_per_frame());
+        assert_eq!(208, h.frame_len());
+
+        // MPEG-1 Layer I, 384 kbit/s, 44.1 kHz, padded.
+        let h = FrameHeader::parse([0xff, 0xff, 0xc2, 0x00]).unwrap();
+        assert_eq!(Layer::Layer1, h.layer);
+        assert_eq!(420, h.frame_len());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        // No sync.
+        assert_eq!(None, FrameHeader::parse([0xfe, 0xfb, 0x90, 0x64]));
+        // Reserved version and layer.
+        assert_eq!(None, FrameHeader::parse([0xff, 0xeb, 0x90, 0x64]));
+        assert_eq!(None, FrameHeader::parse([0xff, 0xf9, 0x90, 0x64]));
+        // Free format and bad bitrate.
+        assert_eq!(None, FrameHeader::parse([0xff, 0xfb, 0x00, 0x64]));
+        assert_eq!(None, FrameHeader::parse([0xff, 0xfb, 0xf0, 0x64]));
+        // Reserved sample rate.
+        assert_eq!(None, FrameHeader::parse([0xff, 0xfb, 0x9c, 0x64]));
+    }
+
+    #[test]
+    fn stream_len() {
+        // 10 seconds at 44.1 kHz is 382.8 frames.
+        assert_eq!(383, DEFAULT_FORMAT.frames_for_duration(10_000));
+        // Padded frames are accounted for: 417.96 bytes per frame on average.
+        assert_eq!(417_959, DEFAULT_FORMAT.stream_len(1000));
+        assert_eq!(1000, DEFAULT_FORMAT.frames_for_len(417_959));
+    }
+
+    #[test]
+    fn generated_frames() {
+        // The padding and CRC bits are cleared.
+        let h = FrameHeader::parse([0xff, 0xfa, 0x92, 0x64]).unwrap();
+        let frame = zero_frame(&h);
+        assert_eq!(417, frame.len());
+        assert_eq!([0xff, 0xfb, 0x90, 0x64], frame[..4]);
+
+        let info = cbr_header(&DEFAULT_FORMAT, 1000, 417_959, &LameTag::new(576, 1000));
+        assert_eq!(417, info.len());
+        assert_eq!(b"Info", &info[0x24..0x28]);
+        assert_eq!(1000u32.to_be_bytes(), info[0x2c..0x30]);
+        assert_eq!(418_376u32.to_be_bytes(), info[0x30..0x34]);
+        assert_eq!([0, 3, 5, 8, 10], info[0x34..0x39]);
+        assert_eq!(253, info[0x97]);
+
+        let lame = read_lame_tag(&info).unwrap();
+        assert!(lame.encoder().starts_with(b"scfs"));
+        assert_eq!(576, lame.delay());
+        assert_eq!(1000, lame.padding());
+        assert_eq!(crc16(&info[..0xbe]).to_be_bytes(), info[0xbe..0xc0]);
+
+        // Mono streams have a shorter side info section.
+        let mono = FrameHeader::parse([0xff, 0xfb, 0x90, 0xc4]).unwrap();
+        let info = cbr_header(&mono, 1, 1, &LameTag::new(0, 0));
+        assert_eq!(b"Info", &info[0x15..0x19]);
+        assert!(read_lame_tag(&info).is_some());
+    }
+
+    #[test]
+    fn info_frame_fits_tag() {
+        // At 8 kbit/s, 8 kHz frames are too small to hold the tag, so a higher bitrate is used.
+        let h = FrameHeader::parse([0xff, 0xe3, 0x1a, 0xc4]).unwrap();
+        assert_eq!(73, h.frame_len());
+        let info = cbr_header(&h, 1, 1, &LameTag::new(0, 0));
+        let info_header = FrameHeader::parse([info[0], info[1], info[2], info[3]]).unwrap();
+        assert_eq!(h.sample_rate, info_header.sample_rate);
+        assert!(!info_header.padding);
+        assert!(info.len() >= 13 + INFO_TAG_LEN + LAME_TAG_LEN);
+        assert_eq!(info_header.frame_len() as usize, info.len());
+    }
+
+    /// The start of the Info frame of a stream encoded by LAME 3.99, as in the comment above.
+    fn lame_frame() -> Vec<u8> {
+        let mut frame = vec![0; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x64]);
+        frame[0x24..0xc0].copy_from_slice(&[
+            0x49, 0x6e, 0x66, 0x6f, 0x00, 0x00, 0x00, 0x0f, 0x00, 0x00, 0x25, 0xbf, 0x00, 0x3d,
+            0xa1, 0xf4, 0x00, 0x03, 0x05, 0x08, 0x0b, 0x0d, 0x10, 0x12, 0x15, 0x17, 0x1a, 0x1c,
+            0x1f, 0x21, 0x24, 0x26, 0x29, 0x2c, 0x2e, 0x31, 0x33, 0x36, 0x38, 0x3b, 0x3d, 0x40,
+            0x42, 0x45, 0x48, 0x4a, 0x4d, 0x4f, 0x52, 0x54, 0x57, 0x59, 0x5c, 0x5e, 0x61, 0x64,
+            0x66, 0x69, 0x6b, 0x6e, 0x70, 0x73, 0x75, 0x78, 0x7a, 0x7d, 0x80, 0x82, 0x85, 0x87,
+            0x8a, 0x8c, 0x8f, 0x91, 0x94, 0x96, 0x99, 0x9b, 0x9e, 0xa1, 0xa3, 0xa6, 0xa8, 0xab,
+            0xad, 0xb0, 0xb2, 0xb5, 0xb7, 0xba, 0xbd, 0xbf, 0xc2, 0xc4, 0xc7, 0xc9, 0xcc, 0xce,
+            0xd1, 0xd3, 0xd6, 0xd9, 0xdb, 0xde, 0xe0, 0xe3, 0xe5, 0xe8, 0xea, 0xed, 0xef, 0xf2,
+            0xf5, 0xf7, 0xfa, 0xfc, 0x00, 0x00, 0x00, 0x39, 0x4c, 0x41, 0x4d, 0x45, 0x33, 0x2e,
+            0x39, 0x39, 0x72, 0x01, 0xaa, 0x00, 0x00, 0x00, 0x00, 0x2e, 0x48, 0x00, 0x00, 0x14,
+            0x80, 0x24, 0x04, 0x9b, 0x4e, 0x00, 0x00, 0x80, 0x00, 0x3d, 0xa1, 0xf4, 0xed, 0x2b,
+            0x38, 0xfc,
+        ]);
+        frame
+    }
+
+    #[test]
+    fn upstream_lame_tag() {
+        let frame = lame_frame();
+        let lame = read_lame_tag(&frame).unwrap();
+        assert_eq!(b"LAME3.99r", lame.encoder());
+        assert_eq!(576, lame.delay());
+        assert_eq!(1179, lame.padding());
+        assert_eq!(0x38fc, crc16(&frame[..0xbe]));
+
+        // The upstream tag is carried over, apart from the fields describing the stream.
+        let info = cbr_header(&DEFAULT_FORMAT, 9663, 4_038_739, &lame);
+        assert_eq!(frame[0x9c..0xbc], info[0x9c..0xbc]);
+        assert_eq!([0, 0], info[0xbc..0xbe]);
+
+        // Frames without a tag.
+        assert_eq!(None, read_lame_tag(&zero_frame(&DEFAULT_FORMAT)));
+        assert_eq!(None, read_lame_tag(&frame[..0xa0]));
+    }
+
+    /// Builds a stream from a prefix and a single audio frame, which is filled with 0xaa.
+    fn stream(prefix: &[&[u8]]) -> Vec<u8> {
+        let mut audio = zero_frame(&DEFAULT_FORMAT);
+        audio[4..].iter_mut().for_each(|b| *b = 0xaa);
+        let mut buf = prefix.concat();
+        buf.extend(audio);
+        buf
+    }
+
+    fn id3_tag(size: u8, footer: bool) -> Vec<u8> {
+        let flags = if footer { ID3V2_FOOTER_FLAG } else { 0 };
+        let mut tag = vec![b'I', b'D', b'3', 4, 0, flags, 0, 0, 1, size];
+        tag.resize(10 + 128 + size as usize, 0);
+        if footer {
+            tag.extend_from_slice(b"3DI\x04\x00\x10\x00\x00\x01");
+            tag.push(size);
+        }
+        tag
+    }
+
+    fn prefix_of(stream: &[u8]) -> StreamPrefix {
+        let mut r = io::Cursor::new(stream);
+        let prefix = read_stream_prefix(&mut r).unwrap();
+        // The audio starts at the returned offset.
+        assert_eq!(0xaa, stream[prefix.audio_offset() as usize + 4]);
+        prefix
+    }
+
+    #[test]
+    fn prefix_info_frame() {
+        let info = cbr_header(&DEFAULT_FORMAT, 1, 417, &LameTag::new(576, 1000));
+        let prefix = prefix_of(&stream(&[&info]));
+        assert_eq!(0, prefix.id3_len);
+        assert_eq!(417, prefix.info_frame_len);
+        assert_eq!(DEFAULT_FORMAT, prefix.format);
+        assert_eq!(Some(576), prefix.lame.map(|t| t.delay()));
+
+        let lame = lame_frame();
+        assert_eq!(
+            Some(1179),
+            prefix_of(&stream(&[&lame])).lame.map(|t| t.padding())
+        );
+    }
+
+    #[test]
+    fn prefix_id3() {
+        let info = cbr_header(&DEFAULT_FORMAT, 1, 417, &LameTag::new(0, 0));
+        let prefix = prefix_of(&stream(&[&id3_tag(5, false), &info]));
+        assert_eq!(10 + 128 + 5, prefix.id3_len);
+        assert_eq!(417, prefix.info_frame_len);
+
+        let prefix = prefix_of(&stream(&[&id3_tag(5, true), &info]));
+        assert_eq!(10 + 128 + 5 + 10, prefix.id3_len);
+
+        // A tag that is directly followed by audio.
+        let prefix = prefix_of(&stream(&[&id3_tag(0, false)]));
+        assert_eq!(138, prefix.audio_offset());
+        assert_eq!(None, prefix.lame);
+    }
+
+    #[test]
+    fn prefix_vbri() {
+        let mut vbri = zero_frame(&DEFAULT_FORMAT);
+        vbri[VBRI_OFFSET..VBRI_OFFSET + 4].copy_from_slice(b"VBRI");
+        let prefix = prefix_of(&stream(&[&vbri]));
+        assert_eq!(417, prefix.info_frame_len);
+        assert_eq!(None, prefix.lame);
+    }
+
+    #[test]
+    fn prefix_encoding() {
+        let info = cbr_header(&DEFAULT_FORMAT, 1, 417, &LameTag::new(576, 1000));
+        let prefix = prefix_of(&stream(&[&id3_tag(5, false), &info]));
+        assert_eq!(Some(prefix), StreamPrefix::decode(&prefix.encode()));
+
+        let prefix = prefix_of(&stream(&[]));
+        assert_eq!(Some(prefix), StreamPrefix::decode(&prefix.encode()));
+
+        let encoded = prefix.encode();
+        assert_eq!(None, StreamPrefix::decode(&encoded[..19]));
+        assert_eq!(
+            None,
+            StreamPrefix::decode(&[&encoded[..], &[0; 3]].concat())
+        );
+    }
+
+    #[test]
+    fn prefix_audio() {
+        let prefix = prefix_of(&stream(&[]));
+        assert_eq!(0, prefix.audio_offset());
+
+        let err = read_stream_prefix(&b"not an mpeg stream"[..]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
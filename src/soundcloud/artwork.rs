@@ -0,0 +1,87 @@
+use crate::soundcloud::util::http::retry_execute;
+use crate::soundcloud::*;
+
+/// The sizes in which artwork and avatars are served, selected by the suffix of the URL. The API
+/// always returns the "large" variant.
+const SIZES: &[(u32, &str)] = &[
+    (32, "small"),
+    (47, "badge"),
+    (67, "t67x67"),
+    (100, "large"),
+    (300, "t300x300"),
+    (400, "crop"),
+    (500, "t500x500"),
+];
+
+/// The largest width and height in which artwork is served.
+pub const MAX_ARTWORK_SIZE: u32 = SIZES[SIZES.len() - 1].0;
+
+/// Rewrites an artwork URL to point to the largest variant that does not exceed the specified
+/// width and height.
+pub(crate) fn sized_url(url: &str, max_size: u32) -> String {
+    let variant = SIZES
+        .iter()
+        .rev()
+        .find(|(size, _)| *size <= max_size)
+        .unwrap_or(&SIZES[0])
+        .1;
+    match url.rfind("-large.") {
+        Some(i) => format!("{}-{}{}", &url[..i], variant, &url[i + "-large".len()..]),
+        None => url.to_string(),
+    }
+}
+
+/// Downloads an image, returning its contents and MIME type.
+pub(crate) fn download(url: &str) -> Result<(Vec<u8>, String), Error> {
+    info!("querying GET {}", url);
+    let mut resp =
+        retry_execute(default_client(), default_client().get(url).build()?)?.error_for_status()?;
+
+    let mime_type = resp
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .map(ToString::to_string)
+        .unwrap_or_else(|| "image/jpg".to_string());
+    let mut data = Vec::new();
+    resp.copy_to(&mut data)?;
+    Ok((data, mime_type))
+}
+
+/// Asks for the size of an image without downloading it. Returns None if the server does not
+/// tell.
+pub(crate) fn content_length(url: &str) -> Result<Option<u64>, Error> {
+    info!("querying HEAD {}", url);
+    let resp =
+        retry_execute(default_client(), default_client().head(url).build()?)?.error_for_status()?;
+    Ok(resp.content_length())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sized() {
+        let url = "https://i1.sndcdn.com/artworks-000123-abc-large.jpg";
+        assert_eq!(
+            "https://i1.sndcdn.com/artworks-000123-abc-t500x500.jpg",
+            sized_url(url, 500)
+        );
+        assert_eq!(
+            "https://i1.sndcdn.com/artworks-000123-abc-t300x300.jpg",
+            sized_url(url, 399)
+        );
+        assert_eq!(
+            "https://i1.sndcdn.com/artworks-000123-abc-small.jpg",
+            sized_url(url, 10)
+        );
+        assert_eq!(
+            "https://i1.sndcdn.com/artworks-000123-abc-original.png",
+            sized_url(
+                "https://i1.sndcdn.com/artworks-000123-abc-original.png",
+                500
+            )
+        );
+    }
+}
@@ -0,0 +1,12 @@
+#[derive(Clone, Debug, Deserialize)]
+pub struct Comment {
+    pub body: String,
+    /// The position in the track that the comment was placed at in milliseconds, if any.
+    pub timestamp: Option<i64>,
+    pub user: CommentUser,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CommentUser {
+    pub username: String,
+}
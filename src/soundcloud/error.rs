@@ -7,6 +7,7 @@ use std::io;
 pub enum Error {
     Login,
     ArtworkNotAvailable,
+    WaveformNotAvailable,
 
     IOError(io::Error),
 
@@ -14,19 +15,45 @@ pub enum Error {
     ReqwestInvalidHeader(reqwest::header::InvalidHeaderValue),
     ReqwestUrlParseError(url::ParseError),
 
-    MalformedResponse {
-        method: reqwest::Method,
-        url: reqwest::Url,
-        body: String,
-        error: Box<dyn error::Error + Send + Sync>,
-    },
+    MalformedResponse(Box<MalformedResponse>),
 
     Generic(String),
 }
 
+/// A response that could not be decoded. It is kept behind a box so results that may carry any
+/// `Error` stay small.
+#[derive(Debug)]
+pub struct MalformedResponse {
+    pub method: reqwest::Method,
+    pub url: reqwest::Url,
+    pub body: String,
+    pub error: Box<dyn error::Error + Send + Sync>,
+}
+
+impl Error {
+    /// Whether the server responded that the requested object does not exist.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, Error::ReqwestError(err) if err.status() == Some(reqwest::StatusCode::NOT_FOUND))
+    }
+}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        match self {
+            Error::Login => write!(f, "login failed"),
+            Error::ArtworkNotAvailable => write!(f, "artwork is not available"),
+            Error::WaveformNotAvailable => write!(f, "waveform is not available"),
+            Error::IOError(err) => write!(f, "io: {}", err),
+            Error::ReqwestError(err) => write!(f, "http: {}", err),
+            Error::ReqwestInvalidHeader(err) => write!(f, "http header: {}", err),
+            Error::ReqwestUrlParseError(err) => write!(f, "url: {}", err),
+            Error::MalformedResponse(res) => write!(
+                f,
+                "malformed response to {} {}: {} (body: {:?})",
+                res.method, res.url, res.error, res.body
+            ),
+            Error::Generic(msg) => write!(f, "{}", msg),
+        }
     }
 }
 
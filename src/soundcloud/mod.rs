@@ -1,9 +1,14 @@
+mod artwork;
+mod comment;
 mod error;
 mod format;
+mod playlist;
 mod track;
 mod user;
 mod util;
+mod waveform;
 
+use self::error::MalformedResponse;
 use self::util::http::retry_execute;
 use lazy_static::lazy_static;
 use log::*;
@@ -12,13 +17,23 @@ use regex::bytes::Regex;
 use reqwest::blocking::{self, RequestBuilder};
 use reqwest::{header, Method, Url};
 use serde::de::DeserializeOwned;
+use serde::Serialize;
 use std::fmt;
 use std::str;
 use url;
 
+pub use self::artwork::MAX_ARTWORK_SIZE;
+pub use self::comment::Comment;
 pub use self::error::Error;
+pub use self::playlist::Playlist;
 pub use self::track::Track;
 pub use self::user::User;
+pub use self::util::fixture::{set_fixtures, Fixtures};
+pub use self::util::http::request_stats;
+pub use self::waveform::Waveform;
+
+#[cfg(test)]
+pub(crate) use self::util::fixture::{lock_fixtures, test_fixtures};
 
 const USER_AGENT: &str = "Mozilla/5.0 (X11; Linux x86_64; rv:71.0) Gecko/20100101 Firefox/71.0";
 const PAGE_MAX_SIZE: u64 = 200;
@@ -156,6 +171,26 @@ impl Client {
         base_url: impl AsRef<str>,
     ) -> Result<T, Error> {
         let (req, url) = self.request(method.clone(), base_url)?;
+        self.fetch(method, url, req)
+    }
+
+    /// Like `query`, but sends a JSON body along with the request.
+    pub(crate) fn query_json<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        base_url: impl AsRef<str>,
+        body: &impl Serialize,
+    ) -> Result<T, Error> {
+        let (req, url) = self.request(method.clone(), base_url)?;
+        self.fetch(method, url, req.json(body))
+    }
+
+    fn fetch<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        url: Url,
+        req: RequestBuilder,
+    ) -> Result<T, Error> {
         info!("querying {} {}", method, url);
         let mut buf = Vec::new();
         retry_execute(&self.client, req.build()?)?
@@ -168,15 +203,56 @@ impl Client {
                 let body = String::from_utf8_lossy(&buf[..]);
                 warn!("bad body: {}", body);
                 warn!("bad body error: {}", err);
-                Err(Error::MalformedResponse {
+                Err(Error::MalformedResponse(Box::new(MalformedResponse {
                     method,
                     url,
                     body: body.to_string(),
                     error: Box::new(err),
-                })
+                })))
             }
         }
     }
+
+    /// The account that the client is logged in as.
+    pub fn me(&self) -> Result<User, Error> {
+        self.query(Method::GET, "https://api.soundcloud.com/me")
+    }
+
+    /// Adds a track to the favorites of the logged in account.
+    pub fn like(&self, track: &Track) -> Result<(), Error> {
+        let url = format!("https://api.soundcloud.com/me/favorites/{}", track.id);
+        self.execute(Method::PUT, url)
+    }
+
+    /// Removes a track from the favorites of the logged in account.
+    pub fn unlike(&self, track: &Track) -> Result<(), Error> {
+        let url = format!("https://api.soundcloud.com/me/favorites/{}", track.id);
+        self.execute(Method::DELETE, url)
+    }
+
+    /// Follows a user with the logged in account.
+    pub fn follow(&self, user: &User) -> Result<(), Error> {
+        let url = format!("https://api.soundcloud.com/me/followings/{}", user.id);
+        self.execute(Method::PUT, url)
+    }
+
+    /// Stops following a user with the logged in account.
+    pub fn unfollow(&self, user: &User) -> Result<(), Error> {
+        let url = format!("https://api.soundcloud.com/me/followings/{}", user.id);
+        self.execute(Method::DELETE, url)
+    }
+
+    /// Performs a request of which only the status is of interest.
+    pub(crate) fn execute(
+        &self,
+        method: reqwest::Method,
+        base_url: impl AsRef<str>,
+    ) -> Result<(), Error> {
+        let (req, url) = self.request(method.clone(), base_url)?;
+        info!("querying {} {}", method, url);
+        retry_execute(&self.client, req.build()?)?.error_for_status()?;
+        Ok(())
+    }
 }
 
 impl fmt::Debug for Client {
@@ -294,9 +370,24 @@ impl<T: DeserializeOwned + Send> Page<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::soundcloud::util::fixture::{lock_fixtures, test_fixtures};
 
     #[test]
     fn anonymous_client() {
+        let _fixtures = lock_fixtures(None);
         Client::anonymous().unwrap();
     }
+
+    #[test]
+    fn replay() {
+        let _fixtures = lock_fixtures(Some(Fixtures::Replay(test_fixtures())));
+        let client = Client::anonymous().unwrap();
+        assert_eq!("test-client-id", client.client_id);
+
+        let user = User::by_name(&client, "wright-and-bastard").unwrap();
+        assert_eq!(14559536, user.id);
+        let tracks = user.tracks(&client).unwrap();
+        let permalinks: Vec<_> = tracks.iter().map(|t| t.permalink.as_str()).collect();
+        assert_eq!(vec!["the-fat-dandy-butterfly-slims"], permalinks);
+    }
 }
@@ -0,0 +1,81 @@
+use super::{artwork, format, Client, Error, Track};
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde_json::json;
+
+/// A playlist, which SoundCloud calls a set.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Playlist {
+    pub id: i64,
+    pub title: String,
+    pub permalink: String,
+    pub permalink_url: String,
+    pub user_id: i64,
+    #[serde(with = "format::date")]
+    pub created_at: DateTime<Utc>,
+    #[serde(with = "format::date")]
+    pub last_modified: DateTime<Utc>,
+    #[serde(default, with = "format::empty_str_as_none")]
+    artwork_url: Option<String>,
+    /// The tracks in the order of the set.
+    #[serde(default)]
+    pub tracks: Vec<Track>,
+}
+
+impl Playlist {
+    pub fn by_id(client: &Client, id: i64) -> Result<Playlist, Error> {
+        let url = format!("https://api.soundcloud.com/playlists/{}", id);
+        client.query(Method::GET, url)
+    }
+
+    /// Creates an empty set owned by the logged in account. New sets are private so nothing is
+    /// published by accident.
+    pub fn create(client: &Client, title: &str, permalink: &str) -> Result<Playlist, Error> {
+        let body = json!({
+            "playlist": {
+                "title": title,
+                "permalink": permalink,
+                "sharing": "private",
+                "tracks": [],
+            },
+        });
+        client.query_json(Method::POST, "https://api.soundcloud.com/playlists", &body)
+    }
+
+    /// Replaces the tracks of the set, returning the updated set.
+    pub fn set_tracks(&self, client: &Client, track_ids: &[i64]) -> Result<Playlist, Error> {
+        let tracks: Vec<_> = track_ids.iter().map(|id| json!({ "id": id })).collect();
+        let body = json!({ "playlist": { "tracks": tracks } });
+        let url = format!("https://api.soundcloud.com/playlists/{}", self.id);
+        client.query_json(Method::PUT, url, &body)
+    }
+
+    pub fn delete(&self, client: &Client) -> Result<(), Error> {
+        let url = format!("https://api.soundcloud.com/playlists/{}", self.id);
+        client.execute(Method::DELETE, url)
+    }
+
+    pub fn track_ids(&self) -> Vec<i64> {
+        self.tracks.iter().map(|track| track.id).collect()
+    }
+
+    /// Downloads the artwork in the largest size that fits in the specified width and height.
+    pub fn artwork(&self, max_size: u32) -> Result<(Vec<u8>, String), Error> {
+        match &self.artwork_url {
+            Some(url) => artwork::download(&artwork::sized_url(url, max_size)),
+            None => Err(Error::ArtworkNotAvailable),
+        }
+    }
+
+    /// The size in bytes of the artwork that would be returned by `artwork`, if the server tells.
+    pub fn artwork_len(&self, max_size: u32) -> Result<Option<u64>, Error> {
+        match &self.artwork_url {
+            Some(url) => artwork::content_length(&artwork::sized_url(url, max_size)),
+            None => Err(Error::ArtworkNotAvailable),
+        }
+    }
+
+    pub fn has_artwork(&self) -> bool {
+        self.artwork_url.is_some()
+    }
+}
@@ -3,12 +3,13 @@ use crate::soundcloud::util::http;
 use crate::soundcloud::*;
 use chrono::{DateTime, Utc};
 use reqwest::Method;
+use serde::{de, Deserialize, Deserializer};
 use std::hash::{Hash, Hasher};
 use std::io;
 
-const AUDIO_CBR_BITRATE: u64 = 128_000;
-
 #[derive(Clone, Debug, Deserialize)]
+// The derived implementation is used by the one below, which also keeps the raw JSON.
+#[serde(remote = "Self")]
 pub struct Track {
     pub id: i64,
     #[serde(with = "format::date")]
@@ -68,19 +69,91 @@ pub struct Track {
     pub permalink_url: String,
     #[serde(default, with = "format::empty_str_as_none")]
     artwork_url: Option<String>,
-    //"waveform_url": "https://w1.sndcdn.com/17huh4rFYXFb_m.png",
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub waveform_url: Option<String>,
     //"stream_url": "https://api.soundcloud.com/tracks/515639547/stream",
-    //"playback_count": 0,
+    pub playback_count: Option<i64>,
     //"download_count": 0,
     //"favoritings_count": 384,
-    //"comment_count": 31,
-    //"likes_count": 384,
-    //"reposts_count": 0,
-    //"policy": "ALLOW",
+    pub comment_count: Option<i64>,
+    pub likes_count: Option<i64>,
+    pub reposts_count: Option<i64>,
+    #[serde(default, with = "format::empty_str_as_none")]
+    pub policy: Option<String>,
     //"monetization_model": "NOT_APPLICABLE"
+    /// The object as it was returned by the API, including the fields that are not listed above.
+    #[serde(skip)]
+    pub raw: serde_json::Value,
+}
+
+impl<'de> Deserialize<'de> for Track {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let raw = serde_json::Value::deserialize(deserializer)?;
+        let mut track = Track::deserialize(&raw).map_err(de::Error::custom)?;
+        track.raw = raw;
+        Ok(track)
+    }
 }
 
 impl Track {
+    /// A track as returned by the API, for tests that do not access the network.
+    #[cfg(test)]
+    pub fn example() -> Self {
+        serde_json::from_value(serde_json::json!({
+            "id": 609233313,
+            "created_at": "2019/05/03 14:21:02 +0000",
+            "user_id": 14559536,
+            "duration": 252290,
+            "commentable": true,
+            "state": "finished",
+            "original_content_size": 10090290,
+            "last_modified": "2019/05/04 09:10:11 +0000",
+            "sharing": "public",
+            "tag_list": "electronic \"Wright and Bastard\" dandy",
+            "permalink": "the-fat-dandy-butterfly-slims",
+            "streamable": true,
+            "embeddable_by": "all",
+            "downloadable": false,
+            "purchase_url": "https://wrightandbastard.bandcamp.com/",
+            "genre": "Electronic",
+            "title": "The Fat Dandy Butterfly Slims",
+            "description": "From the album Drömmar.",
+            "label_name": "Self-released",
+            "release": "WAB-004",
+            "track_type": "original",
+            "key_signature": "Am",
+            "isrc": null,
+            "bpm": 123.4,
+            "release_year": 2019,
+            "release_month": 5,
+            "release_day": 3,
+            "license": "cc-by-nc-sa",
+            "uri": "https://api.soundcloud.com/tracks/609233313",
+            "user": {
+                "id": 14559536,
+                "permalink": "wright-and-bastard",
+                "username": "Wright and Bastard",
+                "last_modified": "2019/05/04 09:10:11 +0000",
+                "uri": "https://api.soundcloud.com/users/14559536",
+                "permalink_url": "https://soundcloud.com/wright-and-bastard",
+                "avatar_url": "https://i1.sndcdn.com/avatars-000123-large.jpg"
+            },
+            "permalink_url": "https://soundcloud.com/wright-and-bastard/the-fat-dandy-butterfly-slims",
+            "artwork_url": null,
+            "waveform_url": "https://w1.sndcdn.com/abcdef_m.png",
+            "playback_count": 1234,
+            "comment_count": 7,
+            "likes_count": 56,
+            "reposts_count": 3,
+            "policy": "ALLOW",
+            "monetization_model": "NOT_APPLICABLE"
+        }))
+        .unwrap()
+    }
+
     #[cfg(test)]
     pub fn by_id(client: &Client, id: i64) -> Result<Self, Error> {
         let url = format!("https://api.soundcloud.com/tracks/{}", id);
@@ -136,37 +209,77 @@ impl Track {
         Ok(http::RangeSeeker::new(default_client(), req))
     }
 
-    pub fn audio_size(&self) -> u64 {
-        self.duration_ms as u64 * AUDIO_CBR_BITRATE / 1000 / 8
+    /// Downloads the artwork in the largest size that fits in the specified width and height.
+    pub fn artwork(&self, max_size: u32) -> Result<(Vec<u8>, String), Error> {
+        match &self.artwork_url {
+            Some(url) => artwork::download(&artwork::sized_url(url, max_size)),
+            None => Err(Error::ArtworkNotAvailable),
+        }
+    }
+
+    /// The size in bytes of the artwork that would be returned by `artwork`, if the server tells.
+    pub fn artwork_len(&self, max_size: u32) -> Result<Option<u64>, Error> {
+        match &self.artwork_url {
+            Some(url) => artwork::content_length(&artwork::sized_url(url, max_size)),
+            None => Err(Error::ArtworkNotAvailable),
+        }
+    }
+
+    pub fn has_artwork(&self) -> bool {
+        self.artwork_url.is_some()
+    }
+
+    pub fn comments(&self, client: &Client) -> Result<Vec<Comment>, Error> {
+        let url = format!("https://api.soundcloud.com/tracks/{}/comments", self.id);
+        Page::all_with_size_hint(client, url, self.comment_count.unwrap_or(0) as u64)
     }
 
-    pub fn artwork(&self) -> Result<(Vec<u8>, String), Error> {
-        let url = match &self.artwork_url {
-            Some(v) => v,
-            None => return Err(Error::ArtworkNotAvailable),
-        };
+    pub fn waveform(&self) -> Result<Waveform, Error> {
+        match &self.waveform_url {
+            Some(url) => waveform::download(url),
+            None => Err(Error::WaveformNotAvailable),
+        }
+    }
 
-        // "large.jpg" is actually a 100x100 image. We can tweak the URL to point to a larger
-        // image instead.
-        let url = if url.ends_with("-large.jpg") {
-            format!("{}-t500x500.jpg", url.trim_end_matches("-large.jpg"))
-        } else {
-            url.to_string()
-        };
+    /// Downloads the waveform as the PNG image that is shown on the website.
+    pub fn waveform_image(&self) -> Result<(Vec<u8>, String), Error> {
+        match &self.waveform_url {
+            Some(url) => artwork::download(url),
+            None => Err(Error::WaveformNotAvailable),
+        }
+    }
 
-        info!("querying GET {}", url);
-        let mut resp = retry_execute(default_client(), default_client().get(&url).build()?)?
-            .error_for_status()?;
+    /// The size in bytes of the image that would be returned by `waveform_image`, if the server
+    /// tells.
+    pub fn waveform_image_len(&self) -> Result<Option<u64>, Error> {
+        match &self.waveform_url {
+            Some(url) => artwork::content_length(url),
+            None => Err(Error::WaveformNotAvailable),
+        }
+    }
 
-        let mime_type = resp
-            .headers()
-            .get(header::CONTENT_TYPE)
-            .and_then(|h| h.to_str().ok())
-            .map(ToString::to_string)
-            .unwrap_or_else(|| "image/jpg".to_string());
-        let mut data = Vec::new();
-        resp.copy_to(&mut data)?;
-        Ok((data, mime_type))
+    /// Splits the tag list into separate tags. Tags are separated by spaces and tags that contain
+    /// spaces themselves are quoted.
+    pub fn tags(&self) -> Vec<&str> {
+        let mut tags = Vec::new();
+        let mut rest = self.tag_list.trim_start();
+        while !rest.is_empty() {
+            let (tag, tail) = match rest.strip_prefix('"') {
+                Some(quoted) => match quoted.find('"') {
+                    Some(i) => (&quoted[..i], &quoted[i + 1..]),
+                    None => (quoted, ""),
+                },
+                None => match rest.find(' ') {
+                    Some(i) => (&rest[..i], &rest[i..]),
+                    None => (rest, ""),
+                },
+            };
+            if !tag.is_empty() {
+                tags.push(tag);
+            }
+            rest = tail.trim_start();
+        }
+        tags
     }
 }
 
@@ -195,14 +308,37 @@ pub struct TrackUser {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::soundcloud::util::fixture::lock_fixtures;
     use io::Read;
 
+    #[test]
+    fn raw() {
+        let track = Track::example();
+        assert_eq!(Some(7), track.comment_count);
+        assert_eq!(Some("ALLOW"), track.policy.as_deref());
+        assert_eq!(609233313, track.raw["id"]);
+        assert_eq!("NOT_APPLICABLE", track.raw["monetization_model"]);
+    }
+
+    #[test]
+    fn tags() {
+        let mut track = Track::example();
+        assert_eq!(
+            vec!["electronic", "Wright and Bastard", "dandy"],
+            track.tags()
+        );
+
+        track.tag_list = "  \"\" one  \"two three".to_string();
+        assert_eq!(vec!["one", "two three"], track.tags());
+    }
+
     #[test]
     fn get_audio() {
         // https://soundcloud.com/wright-and-bastard/the-fat-dandy-butterfly-slims
         // CC BY-NC-SA 3.0
         let id = 609233313;
 
+        let _fixtures = lock_fixtures(None);
         let client = Client::anonymous().unwrap();
         let track = Track::by_id(&client, id).unwrap();
 
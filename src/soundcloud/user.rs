@@ -1,4 +1,4 @@
-use super::{format, Client, Error, Page, Track};
+use super::{artwork, format, Client, Error, Page, Playlist, Track};
 use chrono::{DateTime, Utc};
 use reqwest::Method;
 use std::hash::{Hash, Hasher};
@@ -80,6 +80,23 @@ impl User {
         let url = format!("https://api.soundcloud.com/users/{}/followings", self.id);
         Page::all_with_size_hint(client, url, self.followings_count as u64)
     }
+
+    /// The sets of the user. Private sets are included for the logged in account.
+    pub fn playlists(&self, client: &Client) -> Result<Vec<Playlist>, Error> {
+        let url = format!("https://api.soundcloud.com/users/{}/playlists", self.id);
+        let count = self.playlist_count + self.private_playlists_count.unwrap_or(0);
+        Page::all_with_size_hint(client, url, count as u64)
+    }
+
+    /// Downloads the avatar in the largest size that fits in the specified width and height.
+    pub fn avatar(&self, max_size: u32) -> Result<(Vec<u8>, String), Error> {
+        artwork::download(&artwork::sized_url(&self.avatar_url, max_size))
+    }
+
+    /// The size in bytes of the avatar that would be returned by `avatar`, if the server tells.
+    pub fn avatar_len(&self, max_size: u32) -> Result<Option<u64>, Error> {
+        artwork::content_length(&artwork::sized_url(&self.avatar_url, max_size))
+    }
 }
 
 impl Hash for User {
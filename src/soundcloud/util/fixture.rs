@@ -0,0 +1,319 @@
+//! Recording and replaying of HTTP exchanges.
+//!
+//! When recording, every response that passes through `retry_execute` is written to a directory
+//! as a pair of files: `<key>.json` holds the request line and the response head and `<key>.body`
+//! holds the response body. When replaying, responses are served from such a directory and no
+//! network access is performed at all.
+
+use crate::soundcloud::Error;
+use futures_util::stream;
+use lazy_static::lazy_static;
+use log::*;
+use reqwest::blocking::{Request, Response};
+use reqwest::{header, Body};
+use std::fs;
+use std::io::{self, Seek, SeekFrom};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+#[derive(Clone, Debug)]
+pub enum Fixtures {
+    /// Perform requests as usual and write all exchanges to the directory.
+    Record(PathBuf),
+    /// Serve all responses from the directory.
+    Replay(PathBuf),
+}
+
+lazy_static! {
+    static ref FIXTURES: RwLock<Option<Fixtures>> = RwLock::new(None);
+}
+
+/// Sets the fixture mode for all HTTP requests made from this point on.
+pub fn set_fixtures(fixtures: Option<Fixtures>) {
+    *FIXTURES.write().unwrap() = fixtures;
+}
+
+pub(crate) fn fixtures() -> Option<Fixtures> {
+    FIXTURES.read().unwrap().clone()
+}
+
+/// The directory with the recordings that are replayed by tests.
+#[cfg(test)]
+pub(crate) fn test_fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures")
+}
+
+/// Sets the fixture mode for a test. The mode is global, so tests that make requests hold on to
+/// the returned guard to keep others from changing it.
+#[cfg(test)]
+pub(crate) fn lock_fixtures(fixtures: Option<Fixtures>) -> std::sync::MutexGuard<'static, ()> {
+    lazy_static! {
+        static ref TEST_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    }
+    let guard = TEST_LOCK
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+    set_fixtures(fixtures);
+    guard
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    method: String,
+    url: String,
+    range: Option<String>,
+    status: u16,
+    headers: Vec<(String, String)>,
+}
+
+/// Writes the response to the fixture directory. A response that is equivalent to the original is
+/// returned.
+///
+/// Bodies may be entire audio streams, so they are copied to disk and read back from there rather
+/// than buffered in memory.
+pub(crate) fn record(
+    dir: &Path,
+    request: &Request,
+    mut response: Response,
+) -> Result<Response, Error> {
+    let exchange = Exchange {
+        method: request.method().to_string(),
+        url: request.url().to_string(),
+        range: range_header(request),
+        status: response.status().as_u16(),
+        headers: response
+            .headers()
+            .iter()
+            // The body has already been decoded and its length is set when replaying.
+            .filter(|(name, _)| {
+                *name != header::CONTENT_LENGTH
+                    && *name != header::CONTENT_ENCODING
+                    && *name != header::TRANSFER_ENCODING
+            })
+            .filter_map(|(name, value)| {
+                let value = value.to_str().ok()?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect(),
+    };
+    let key = fixture_key(&exchange.method, &exchange.url, exchange.range.as_deref());
+    trace!("recording {} {} as {}", exchange.method, exchange.url, key);
+
+    fs::create_dir_all(dir)?;
+    let body_path = dir.join(format!("{}.body", key));
+    let len = response.copy_to(&mut fs::File::create(&body_path)?)?;
+    let meta = serde_json::to_vec_pretty(&exchange)
+        .map_err(|err| Error::Generic(format!("could not encode fixture {}: {}", key, err)))?;
+    fs::write(dir.join(format!("{}.json", key)), meta)?;
+
+    build_response(&exchange, fs::File::open(&body_path)?, len)
+}
+
+/// Looks up the recorded response for the request.
+///
+/// A range request for which no exact recording exists is served from a recording of the full
+/// resource, if there is one. This allows a replay to seek differently from the recording.
+pub(crate) fn replay(dir: &Path, request: &Request) -> Result<Response, Error> {
+    let method = request.method().to_string();
+    let url = request.url().to_string();
+    let range = range_header(request);
+
+    if let Some((exchange, body)) = load(dir, &method, &url, range.as_deref())? {
+        trace!("replaying {} {}", method, url);
+        let len = body.metadata()?.len();
+        return build_response(&exchange, body, len);
+    }
+
+    let range_start = range.as_deref().filter(|r| r.ends_with('-')).and_then(|r| {
+        r.trim_start_matches("bytes=")
+            .trim_end_matches('-')
+            .parse()
+            .ok()
+    });
+    if let Some(start) = range_start {
+        if let Some((mut exchange, mut body)) = load(dir, &method, &url, Some("bytes=0-"))? {
+            trace!("replaying {} {} from offset {}", method, url, start);
+            let len = body.metadata()?.len();
+            if start > len {
+                exchange.status = 416;
+                return build_response(&exchange, io::empty(), 0);
+            }
+            body.seek(SeekFrom::Start(start))?;
+            exchange.status = 206;
+            return build_response(&exchange, body, len - start);
+        }
+    }
+
+    Err(Error::IOError(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!(
+            "no recorded response for {} {} (range: {:?})",
+            method, url, range
+        ),
+    )))
+}
+
+fn load(
+    dir: &Path,
+    method: &str,
+    url: &str,
+    range: Option<&str>,
+) -> Result<Option<(Exchange, fs::File)>, Error> {
+    let key = fixture_key(method, url, range);
+    let meta = match fs::read(dir.join(format!("{}.json", key))) {
+        Ok(v) => v,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let exchange = serde_json::from_slice(&meta)
+        .map_err(|err| Error::Generic(format!("malformed fixture {}: {}", key, err)))?;
+    let body = fs::File::open(dir.join(format!("{}.body", key)))?;
+    Ok(Some((exchange, body)))
+}
+
+/// Builds a response of which the body is read from `body` in chunks as it is consumed.
+fn build_response(
+    exchange: &Exchange,
+    mut body: impl io::Read + Send + Sync + 'static,
+    len: u64,
+) -> Result<Response, Error> {
+    let chunks = iter::from_fn(move || {
+        let mut buf = vec![0; 64 * 1024];
+        match body.read(&mut buf) {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some(Ok(buf))
+            }
+            Err(err) => Some(Err(err)),
+        }
+    });
+
+    let mut builder = http::Response::builder()
+        .status(exchange.status)
+        .header(header::CONTENT_LENGTH, len);
+    for (name, value) in &exchange.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    let res = builder
+        .body(Body::wrap_stream(stream::iter(chunks)))
+        .map_err(|err| Error::Generic(format!("could not build response: {}", err)))?;
+    Ok(Response::from(res))
+}
+
+fn range_header(request: &Request) -> Option<String> {
+    request
+        .headers()
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .map(ToString::to_string)
+}
+
+/// Computes the file name for an exchange. The host is included to make the directory easier to
+/// browse, the rest is a 64 bit FNV-1a hash which is stable across Rust versions.
+fn fixture_key(method: &str, url: &str, range: Option<&str>) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    let parts = [method, " ", url, " ", range.unwrap_or("")];
+    for b in parts.iter().flat_map(|s| s.bytes()) {
+        hash ^= u64::from(b);
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    let host = reqwest::Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(ToString::to_string))
+        .unwrap_or_default();
+    format!("{}-{:016x}", host, hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::io::Read;
+    use std::process;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("soundcloud-fs-fixture-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn test_request(range: Option<&str>) -> Request {
+        let mut req = Request::new(
+            reqwest::Method::GET,
+            "https://example.com/audio.mp3".parse().unwrap(),
+        );
+        if let Some(r) = range {
+            req.headers_mut()
+                .insert(header::RANGE, HeaderValue::from_str(r).unwrap());
+        }
+        req
+    }
+
+    fn store(dir: &Path, range: Option<&str>, status: u16, body: &[u8]) {
+        let exchange = Exchange {
+            method: "GET".to_string(),
+            url: "https://example.com/audio.mp3".to_string(),
+            range: range.map(ToString::to_string),
+            status,
+            headers: vec![("content-type".to_string(), "audio/mpeg".to_string())],
+        };
+        let key = fixture_key(&exchange.method, &exchange.url, range);
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join(format!("{}.body", key)), body).unwrap();
+        fs::write(
+            dir.join(format!("{}.json", key)),
+            serde_json::to_vec(&exchange).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn replay_exact() {
+        let dir = test_dir("exact");
+        store(&dir, None, 200, b"hello");
+
+        let mut res = replay(&dir, &test_request(None)).unwrap();
+        assert_eq!(200, res.status().as_u16());
+        assert_eq!("audio/mpeg", res.headers()[header::CONTENT_TYPE]);
+        assert_eq!("5", res.headers()[header::CONTENT_LENGTH]);
+        let mut buf = Vec::new();
+        res.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"hello", &buf[..]);
+    }
+
+    #[test]
+    fn replay_range_from_full() {
+        let dir = test_dir("range");
+        store(&dir, Some("bytes=0-"), 206, b"0123456789");
+
+        let mut res = replay(&dir, &test_request(Some("bytes=4-"))).unwrap();
+        assert_eq!(206, res.status().as_u16());
+        let mut buf = Vec::new();
+        res.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"456789", &buf[..]);
+
+        let res = replay(&dir, &test_request(Some("bytes=20-"))).unwrap();
+        assert_eq!(416, res.status().as_u16());
+    }
+
+    #[test]
+    fn replay_missing() {
+        let dir = test_dir("missing");
+        match replay(&dir, &test_request(None)) {
+            Err(Error::IOError(err)) => assert_eq!(io::ErrorKind::NotFound, err.kind()),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn key_is_stable() {
+        assert_eq!(
+            "example.com-12f1da98fc49b7ac",
+            fixture_key("GET", "https://example.com/", None)
+        );
+    }
+}
@@ -1,17 +1,56 @@
+use super::fixture::{self, Fixtures};
+use crate::soundcloud::Error;
 use log::*;
 use reqwest::blocking::{Client, Request, Response};
 use reqwest::header::{self, HeaderValue};
 use reqwest::StatusCode;
 use std::io;
 use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::thread;
 use std::time::Duration;
 
-pub fn retry_execute(client: &Client, request: Request) -> reqwest::Result<Response> {
+static REQUESTS: AtomicU64 = AtomicU64::new(0);
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+static FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// Counts of the HTTP requests that have been made, including those for audio, artwork and such.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RequestStats {
+    pub requests: u64,
+    /// Attempts that were made again after a server error or a failure to connect.
+    pub retries: u64,
+    /// Requests that still failed after all attempts.
+    pub failures: u64,
+}
+
+pub fn request_stats() -> RequestStats {
+    RequestStats {
+        requests: REQUESTS.load(Ordering::Relaxed),
+        retries: RETRIES.load(Ordering::Relaxed),
+        failures: FAILURES.load(Ordering::Relaxed),
+    }
+}
+
+pub fn retry_execute(client: &Client, request: Request) -> Result<Response, Error> {
+    REQUESTS.fetch_add(1, Ordering::Relaxed);
+    let fixtures = fixture::fixtures();
+    if let Some(Fixtures::Replay(dir)) = &fixtures {
+        return fixture::replay(dir, &request);
+    }
+
+    // A request that creates something may fail after the server has acted on it. Repeating it
+    // could create a duplicate.
+    let attempts = if request.method().is_idempotent() {
+        5
+    } else {
+        1
+    };
     let mut err = None;
-    for attempt in 0..5 {
+    for attempt in 0..attempts {
         if let Some(err) = &err {
             warn!("query {}: {:?}, retrying", request.url(), err);
+            RETRIES.fetch_add(1, Ordering::Relaxed);
         }
 
         let req = request
@@ -22,11 +61,17 @@ pub fn retry_execute(client: &Client, request: Request) -> reqwest::Result<Respo
                 thread::sleep(Duration::from_millis(100) * (1 << attempt));
                 Some(res.error_for_status().unwrap_err())
             }
-            Ok(res) => return Ok(res),
+            Ok(res) => {
+                return match &fixtures {
+                    Some(Fixtures::Record(dir)) => fixture::record(dir, &request, res),
+                    _ => Ok(res),
+                };
+            }
             Err(err) => Some(err),
         };
     }
-    Err(err.expect("retry loop should run at least once"))
+    FAILURES.fetch_add(1, Ordering::Relaxed);
+    Err(err.expect("retry loop should run at least once").into())
 }
 
 enum State {
@@ -218,6 +263,7 @@ fn valid_offset(offset: i64) -> Result<u64, io::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::soundcloud::util::fixture::lock_fixtures;
     use std::io::{Read, Seek};
 
     fn test_request(size: usize) -> Request {
@@ -247,6 +293,7 @@ mod tests {
     #[test]
     fn test_read_all() {
         const SIZE: usize = 8192;
+        let _fixtures = lock_fixtures(None);
         let client = Client::new();
         let req = test_request(SIZE);
 
@@ -262,6 +309,7 @@ mod tests {
     #[test]
     fn test_read_partial() {
         const SIZE: usize = 8192;
+        let _fixtures = lock_fixtures(None);
         let client = Client::new();
         let req = test_request(SIZE);
 
@@ -280,6 +328,7 @@ mod tests {
     #[test]
     fn test_seek_to_end() {
         const SIZE: usize = 8192;
+        let _fixtures = lock_fixtures(None);
         let client = Client::new();
         let req = test_request(SIZE);
 
@@ -298,6 +347,7 @@ mod tests {
     #[test]
     fn test_probe_size() {
         const SIZE: usize = 8192;
+        let _fixtures = lock_fixtures(None);
         let client = Client::new();
         let req = test_request(SIZE);
 
@@ -319,6 +369,7 @@ mod tests {
     #[test]
     fn test_read_after_seek() {
         const SIZE: usize = 8192;
+        let _fixtures = lock_fixtures(None);
         let client = Client::new();
         let req = test_request(SIZE);
 
@@ -0,0 +1,89 @@
+use crate::soundcloud::util::http::retry_execute;
+use crate::soundcloud::*;
+
+/// The outline of the audio of a track, as drawn by the player on the website.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Waveform {
+    pub height: u32,
+    /// The height of the waveform at every horizontal position, from left to right, from 0 up to
+    /// and including `height`.
+    pub samples: Vec<u32>,
+}
+
+impl Waveform {
+    /// The samples scaled to the range 0.0 to 1.0.
+    pub fn normalized(&self) -> Vec<f32> {
+        let height = self.height.max(1) as f32;
+        self.samples.iter().map(|&s| s as f32 / height).collect()
+    }
+
+    /// The normalized samples, stretched or shrunk to the specified number of samples and limited
+    /// to 1.0.
+    pub fn resampled(&self, len: usize) -> Vec<f32> {
+        let normalized = self.normalized();
+        if normalized.is_empty() {
+            return vec![0.0; len];
+        }
+        (0..len)
+            .map(|i| normalized[i * normalized.len() / len].min(1.0))
+            .collect()
+    }
+}
+
+/// The waveform URL in track objects points to a PNG image. The same data is available as JSON
+/// from another host, e.g. "https://w1.sndcdn.com/abc_m.png" is at
+/// "https://wave.sndcdn.com/abc_m.json".
+pub(crate) fn json_url(waveform_url: &str) -> Option<String> {
+    let name = waveform_url.rsplit('/').next()?.strip_suffix(".png")?;
+    Some(format!("https://wave.sndcdn.com/{}.json", name))
+}
+
+pub(crate) fn download(waveform_url: &str) -> Result<Waveform, Error> {
+    let url = json_url(waveform_url).ok_or(Error::WaveformNotAvailable)?;
+    info!("querying GET {}", url);
+    let waveform = retry_execute(default_client(), default_client().get(&url).build()?)?
+        .error_for_status()?
+        .json()?;
+    Ok(waveform)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url() {
+        assert_eq!(
+            Some("https://wave.sndcdn.com/abcdef_m.json".to_string()),
+            json_url("https://w1.sndcdn.com/abcdef_m.png")
+        );
+        assert_eq!(None, json_url("https://w1.sndcdn.com/abcdef_m"));
+    }
+
+    #[test]
+    fn normalized() {
+        let waveform = Waveform {
+            height: 140,
+            samples: vec![0, 70, 140],
+        };
+        assert_eq!(vec![0.0, 0.5, 1.0], waveform.normalized());
+    }
+
+    #[test]
+    fn resampled() {
+        let waveform = Waveform {
+            height: 140,
+            samples: vec![0, 70, 140, 280],
+        };
+        assert_eq!(vec![0.0, 1.0], waveform.resampled(2));
+        assert_eq!(
+            vec![0.0, 0.0, 0.5, 0.5, 1.0, 1.0, 1.0, 1.0],
+            waveform.resampled(8)
+        );
+        let empty = Waveform {
+            height: 140,
+            samples: vec![],
+        };
+        assert_eq!(vec![0.0; 3], empty.resampled(3));
+    }
+}
@@ -0,0 +1,51 @@
+//! State that is changed while mounted and kept for the next mount. This is a plain text file
+//! that lists the users that are recursed into, one per line.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Reads the users from the state file. A file that does not exist yet lists no users.
+pub fn load_users(path: &Path) -> io::Result<Vec<String>> {
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(s
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub fn save_users(path: &Path, users: &[String]) -> io::Result<()> {
+    let mut contents = users.join("\n");
+    contents.push('\n');
+    // Like cache entries, the file is replaced at once so it is never left partially written.
+    let tmp_path = path.with_extension("tmp");
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn users() {
+        let dir = std::env::temp_dir().join(format!("soundcloud-fs-state-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state");
+
+        assert_eq!(Vec::<String>::new(), load_users(&path).unwrap());
+        let users = vec!["ckiee".to_string(), "wright-and-bastard".to_string()];
+        save_users(&path, &users).unwrap();
+        assert_eq!(users, load_users(&path).unwrap());
+
+        fs::write(&path, "a\n\n  b \n").unwrap();
+        assert_eq!(vec!["a", "b"], load_users(&path).unwrap());
+    }
+}